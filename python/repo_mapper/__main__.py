@@ -8,6 +8,7 @@ if __name__ == "__main__":
     def str_to_list(inp_str: str) -> list[str]:
         return inp_str.split(",")
 
+    # options left as None are read from the repo's config file, or fall back to the defaults
     parser = argparse.ArgumentParser()
 
     parser.add_argument(
@@ -19,38 +20,129 @@ if __name__ == "__main__":
     parser.add_argument(
         "--readme-path",
         type=os.path.abspath,
-        required=True,
-        help="Path to the readme file to add the map to.",
+        default=None,
+        help="Path to the readme file to add the map to. Not needed with `--stdout`.",
     )
     parser.add_argument(
         "--gitignore-path",
         type=os.path.abspath,
-        required=True,
-        help="Path to the .gitignore.",
+        default=None,
+        help="Path to the .gitignore. Defaults to `<repo-root>/.gitignore` if it exists.",
     )
     parser.add_argument(
         "--allowed-exts",
-        default=["py", "md", "toml", "lock", "yaml", "ipynb"],
+        default=None,
         type=str_to_list,
-        help="A comma separated string of extensions to remove. E.g. 'py,rs,toml'. Defaults to: 'py,md,toml,lock,yaml,ipynb'",
+        help="A comma separated string of extensions to keep. E.g. 'py,rs,toml'. Defaults to: 'py,md,toml,lock,yaml,ipynb'",
     )
     parser.add_argument(
         "--ignore-dirs",
-        default=[".git", ".venv", "build", "dist"],
+        default=None,
         type=str_to_list,
-        help="A comma separated string of directories to ignore. E.g. '.venv,target'.",
+        help="A comma separated string of directories to ignore. E.g. '.venv,target'. Defaults to: '.git,.venv,build,dist'",
     )
     parser.add_argument(
         "--ignore-hidden",
-        action="store_true",
+        action=argparse.BooleanOptionalAction,
+        default=None,
         help="Flag to ignore hidden files. E.g. those that start with a '.' like '.env'.",
     )
     parser.add_argument(
         "--dirs-only",
-        action="store_true",
+        action=argparse.BooleanOptionalAction,
+        default=None,
         help="Flag to only map directories instead of files",
     )
+    parser.add_argument(
+        "--git-excludes",
+        action=argparse.BooleanOptionalAction,
+        default=None,
+        help="Flag to also respect `.git/info/exclude` and the global `core.excludesFile`.",
+    )
+    parser.add_argument(
+        "--ignore-files",
+        default=None,
+        type=str_to_list,
+        help="A comma separated string of extra ignore files in gitignore syntax to respect besides `.repomapignore`, only read from the repo root. E.g. '.dockerignore,.npmignore'.",
+    )
+    parser.add_argument(
+        "--git-tracked",
+        action=argparse.BooleanOptionalAction,
+        default=None,
+        help="Flag to map exactly the files in the git index (`git ls-files`) instead of walking the directory. `.gitignore` rules are not applied.",
+    )
+    parser.add_argument(
+        "--symlinks",
+        default=None,
+        choices=["skip", "plain", "list", "follow"],
+        help="How to map symlinks: 'skip' leaves them out, 'plain' shows them as entries, 'list' shows them as entries annotated with their target, 'follow' maps what they point to without looping. Defaults to 'plain'.",
+    )
+    parser.add_argument(
+        "--strict",
+        action=argparse.BooleanOptionalAction,
+        default=None,
+        help="Flag to fail without modifying the README if any directory, entry or ignore file could not be read, instead of only warning.",
+    )
+    parser.add_argument(
+        "--heading",
+        default=None,
+        help="The heading a new map is put under. Defaults to 'Repo map'.",
+    )
+    parser.add_argument(
+        "--heading-level",
+        default=None,
+        type=int,
+        choices=range(1, 7),
+        help="The level of the heading a new map is put under, from 1 to 6. Defaults to 1.",
+    )
+    parser.add_argument(
+        "--insert-position",
+        default=None,
+        help="Where to put a new map: 'end', 'start' or 'after:<heading>' for the end of that heading's section, after any of its subsections. Defaults to 'end'.",
+    )
+    parser.add_argument(
+        "--max-depth",
+        default=None,
+        type=int,
+        help="How many levels of the tree to map, deeper entries are left out. 0 only summarises the root's entries.",
+    )
+    parser.add_argument(
+        "--max-children",
+        default=None,
+        type=int,
+        help="How many entries of a directory to map, the rest are summarised on one line. E.g. '… 240 more files (212 .py, 28 .json)'.",
+    )
+    parser.add_argument(
+        "--annotate",
+        action=argparse.BooleanOptionalAction,
+        default=None,
+        help="Flag to annotate files with the first line of their Python module docstring, Rust `//!` comment, leading JS/TS JSDoc block or Markdown H1.",
+    )
+    parser.add_argument(
+        "--metadata",
+        default=None,
+        type=str_to_list,
+        help="A comma separated string of metadata columns to add after each entry: 'size', 'lines' and/or 'mtime'. Directories show the totals of the files below them.",
+    )
+    parser.add_argument(
+        "--render",
+        default=None,
+        choices=["code", "list", "html"],
+        help="How to render the map in a Markdown README: 'code' as a tree in a code block, or clickable as 'list', a nested list of links, or 'html', a tree in a `<pre>` block with links. Defaults to 'code'.",
+    )
+    parser.add_argument(
+        "--check",
+        action="store_true",
+        help="Flag to only check the map is up to date, printing a diff and failing if it is stale without modifying the README. Useful in CI.",
+    )
+    parser.add_argument(
+        "--stdout",
+        action="store_true",
+        help="Flag to print the map to stdout instead of reading or modifying the README.",
+    )
     args = parser.parse_args()
+    if args.check and args.stdout:
+        parser.error("--check and --stdout can't be used together")
     sys.exit(
         int(
             repo_mapper_py.py_main(
@@ -61,6 +153,21 @@ if __name__ == "__main__":
                 ignore_dirs=args.ignore_dirs,
                 ignore_hidden=args.ignore_hidden,
                 dirs_only=args.dirs_only,
+                git_excludes=args.git_excludes,
+                ignore_files=args.ignore_files,
+                git_tracked=args.git_tracked,
+                symlinks=args.symlinks,
+                strict=args.strict,
+                heading=args.heading,
+                heading_level=args.heading_level,
+                insert_position=args.insert_position,
+                max_depth=args.max_depth,
+                max_children=args.max_children,
+                annotate=args.annotate,
+                metadata=args.metadata,
+                render=args.render,
+                check=args.check,
+                stdout=args.stdout,
             )
         )
     )
@@ -1,34 +1,82 @@
-use crate::core::{adapters::RealFileSystem, domain::RetCode, main};
-use pyo3::prelude::*;
+use crate::core::{
+    adapters::RealFileSystem,
+    config::Options,
+    domain::{InsertPosition, MetadataColumn, RenderMode, SymlinkMode},
+    main,
+};
+use pyo3::{exceptions::PyValueError, prelude::*};
 
+/// Options left as `None` are read from the repo's config file or fall back to the defaults.
 #[pyfunction]
+#[allow(clippy::too_many_arguments)]
 fn py_main(
     repo_root: String,
-    readme_path: String,
-    gitignore_path: String,
-    allowed_exts: Vec<String>,
-    ignore_dirs: Vec<String>,
-    ignore_hidden: bool,
-    dirs_only: bool,
+    readme_path: Option<String>,
+    gitignore_path: Option<String>,
+    allowed_exts: Option<Vec<String>>,
+    ignore_dirs: Option<Vec<String>>,
+    ignore_hidden: Option<bool>,
+    dirs_only: Option<bool>,
+    git_excludes: Option<bool>,
+    ignore_files: Option<Vec<String>>,
+    git_tracked: Option<bool>,
+    symlinks: Option<String>,
+    strict: Option<bool>,
+    heading: Option<String>,
+    heading_level: Option<u8>,
+    insert_position: Option<String>,
+    max_depth: Option<usize>,
+    max_children: Option<usize>,
+    annotate: Option<bool>,
+    metadata: Option<Vec<String>>,
+    render: Option<String>,
+    check: bool,
+    stdout: bool,
 ) -> PyResult<i8> {
+    let symlinks: Option<SymlinkMode> = symlinks
+        .map(|symlinks| symlinks.parse().map_err(PyValueError::new_err))
+        .transpose()?;
+    let insert_position: Option<InsertPosition> = insert_position
+        .map(|position| position.parse().map_err(PyValueError::new_err))
+        .transpose()?;
+    let metadata: Option<Vec<MetadataColumn>> = metadata
+        .map(|columns| {
+            columns
+                .iter()
+                .map(|column| column.parse().map_err(PyValueError::new_err))
+                .collect()
+        })
+        .transpose()?;
+    let render: Option<RenderMode> = render
+        .map(|render| render.parse().map_err(PyValueError::new_err))
+        .transpose()?;
     let mut file_sys = RealFileSystem;
 
-    match main(
-        &mut file_sys,
-        repo_root,
+    let options = Options {
         readme_path,
         gitignore_path,
         allowed_exts,
         ignore_dirs,
         ignore_hidden,
         dirs_only,
-    ) {
-        Ok(RetCode::NoModification) => Ok(0),
-        Ok(RetCode::ModifiedReadme) => Ok(1),
-        Err(RetCode::FailedParsingFile) => Ok(2),
-        Err(RetCode::FailedToWriteReadme) => Ok(3),
-        Err(RetCode::InvalidFilename) => Ok(4),
-        _ => Ok(-1),
+        git_excludes,
+        ignore_files,
+        git_tracked,
+        symlinks,
+        strict,
+        heading,
+        heading_level,
+        insert_position,
+        max_depth,
+        max_children,
+        annotate,
+        metadata,
+        render,
+        ..Options::default()
+    };
+
+    match main(&mut file_sys, repo_root, options, check, stdout) {
+        Ok(ret_code) | Err(ret_code) => Ok(ret_code.code() as i8),
     }
 }
 
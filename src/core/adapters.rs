@@ -1,37 +1,158 @@
 use std::{
-    collections::HashMap,
-    fs, io,
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Write},
     path::{Path, PathBuf},
+    process::Command,
+    sync::{Mutex, PoisonError},
+    time::SystemTime,
 };
 
-use walkdir::WalkDir;
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub path: PathBuf,
+    /// For a symlink, whether it points to a directory.
+    pub is_dir: bool,
+    /// The target of a symlink, as stored in the link.
+    pub link: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMetadata {
+    /// The size in bytes.
+    pub size: u64,
+    /// When the file was last modified, if the platform records it.
+    pub modified: Option<SystemTime>,
+}
+
+/// The reads a walk needs, shared between threads so directories are listed concurrently.
+pub trait DirReader: Sync {
+    /// Lists a directory, an entry that can't be read is an error in the list.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<io::Result<DirEntry>>>;
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+    /// Resolves every symlink in `path`, like `fs::canonicalize`.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+}
 
 pub trait FileSystem {
-    fn list_files(&mut self, path: impl AsRef<Path>) -> Vec<PathBuf>;
+    /// Borrows the file system for reads that can run on several threads at once.
+    fn dir_reader(&mut self) -> impl DirReader + '_;
+    /// Lists the files in the git index below `path`, i.e. what `git ls-files` shows.
+    fn list_git_files(&mut self, path: impl AsRef<Path>) -> io::Result<Vec<PathBuf>>;
     fn read_to_string(&mut self, path: &Path) -> io::Result<String>;
+    /// Resolves every symlink in `path`, like `fs::canonicalize`.
+    fn canonicalize(&mut self, path: &Path) -> io::Result<PathBuf>;
+    /// Whether anything, even a broken symlink, is at `path`.
+    fn exists(&mut self, path: &Path) -> bool;
+    /// The size and modification time of the file at `path`, following symlinks.
+    fn metadata(&mut self, path: &Path) -> io::Result<FileMetadata>;
+    /// Counts the lines of the file at `path`, whether or not it is text.
+    fn count_lines(&mut self, path: &Path) -> io::Result<usize>;
     fn write(&mut self, path: &Path, contents: &str) -> std::result::Result<(), std::io::Error>;
+    fn write_stdout(&mut self, contents: &str) -> io::Result<()>;
 }
 
 pub struct RealFileSystem;
 
+pub struct RealDirReader;
+
+impl DirReader for RealDirReader {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<io::Result<DirEntry>>> {
+        Ok(fs::read_dir(path)?
+            .map(|e| {
+                let e = e?;
+                let file_type = e.file_type()?;
+                let path = e.path();
+                if file_type.is_symlink() {
+                    // a broken link is kept as a file, following it fails later
+                    return Ok(DirEntry {
+                        is_dir: path.is_dir(),
+                        link: Some(fs::read_link(&path)?),
+                        path,
+                    });
+                }
+                Ok(DirEntry {
+                    path,
+                    is_dir: file_type.is_dir(),
+                    link: None,
+                })
+            })
+            .collect())
+    }
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+}
+
 impl FileSystem for RealFileSystem {
-    fn list_files(&mut self, path: impl AsRef<Path>) -> Vec<PathBuf> {
-        WalkDir::new(path)
-            .into_iter()
-            .filter_map(Result::ok)
-            .map(|e| e.path().to_owned())
-            .collect()
+    fn dir_reader(&mut self) -> impl DirReader + '_ {
+        RealDirReader
+    }
+    fn list_git_files(&mut self, path: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+        let path = path.as_ref();
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(path)
+            .args(["ls-files", "-z", "--cached"])
+            .output()?;
+
+        if !output.status.success() {
+            return Err(io::Error::other(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+        Ok(output
+            .stdout
+            .split(|b| *b == b'\0')
+            .filter(|p| !p.is_empty())
+            .map(|p| path.join(String::from_utf8_lossy(p).as_ref()))
+            .collect())
     }
     fn read_to_string(&mut self, path: &Path) -> io::Result<String> {
         fs::read_to_string(path)
     }
+    fn canonicalize(&mut self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+    fn exists(&mut self, path: &Path) -> bool {
+        path.symlink_metadata().is_ok()
+    }
+    fn metadata(&mut self, path: &Path) -> io::Result<FileMetadata> {
+        let metadata = fs::metadata(path)?;
+        Ok(FileMetadata {
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+    fn count_lines(&mut self, path: &Path) -> io::Result<usize> {
+        let bytes = fs::read(path)?;
+        let newlines = bytes.iter().filter(|b| **b == b'\n').count();
+        // like `str::lines`, a last line without a newline still counts
+        Ok(newlines + usize::from(bytes.last().is_some_and(|b| *b != b'\n')))
+    }
     fn write(&mut self, path: &Path, contents: &str) -> std::result::Result<(), std::io::Error> {
         fs::write(path, contents)
     }
+    fn write_stdout(&mut self, contents: &str) -> io::Result<()> {
+        // unlike `println!` this doesn't panic if the pipe is closed early
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "{contents}")?;
+        stdout.flush()
+    }
 }
 
 pub struct FakeFileSystem {
     pub files: HashMap<PathBuf, String>,
+    /// Maps a symlink to the path it points to.
+    pub symlinks: HashMap<PathBuf, PathBuf>,
+    /// Paths that fail to be read, as if permission was denied.
+    pub denied: HashSet<PathBuf>,
+    /// When files were last modified, others have no modification time.
+    pub modified: HashMap<PathBuf, SystemTime>,
+    pub stdout: String,
     pub operations: Vec<String>,
 }
 
@@ -39,9 +160,126 @@ impl FakeFileSystem {
     pub fn new(files: HashMap<PathBuf, String>) -> Self {
         Self {
             files,
+            symlinks: HashMap::new(),
+            denied: HashSet::new(),
+            modified: HashMap::new(),
+            stdout: String::new(),
             operations: Vec::new(),
         }
     }
+
+    fn reader(&mut self) -> FakeDirReader<'_> {
+        FakeDirReader {
+            files: &self.files,
+            symlinks: &self.symlinks,
+            denied: &self.denied,
+            operations: Mutex::new(&mut self.operations),
+        }
+    }
+}
+
+/// Reads the files of a `FakeFileSystem`, still recording every operation.
+pub struct FakeDirReader<'a> {
+    files: &'a HashMap<PathBuf, String>,
+    symlinks: &'a HashMap<PathBuf, PathBuf>,
+    denied: &'a HashSet<PathBuf>,
+    operations: Mutex<&'a mut Vec<String>>,
+}
+
+impl FakeDirReader<'_> {
+    fn record(&self, operation: String) {
+        self.operations
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(operation);
+    }
+
+    fn check_denied(&self, path: &Path) -> io::Result<()> {
+        if self.denied.contains(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Permission denied",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Replaces symlinks in `path` with their targets until none are left.
+    fn resolve(&self, path: &Path) -> io::Result<PathBuf> {
+        let mut path = path.to_path_buf();
+        // the same limit as linux's ELOOP
+        for _ in 0..40 {
+            let link = path
+                .ancestors()
+                .find_map(|anc| self.symlinks.get(anc).map(|target| (anc, target)));
+            let Some((link, target)) = link else {
+                return Ok(path);
+            };
+            path = match path.strip_prefix(link) {
+                Ok(rest) if !rest.as_os_str().is_empty() => target.join(rest),
+                _ => target.clone(),
+            };
+        }
+        Err(io::Error::other("Too many levels of symbolic links"))
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.files
+            .keys()
+            .any(|file| file.starts_with(path) && file != path)
+    }
+}
+
+impl DirReader for FakeDirReader<'_> {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<io::Result<DirEntry>>> {
+        self.record(format!("read_dir: `{}`", &path.display()));
+        self.check_denied(path)?;
+        let resolved = self.resolve(path)?;
+        let mut entries: HashMap<PathBuf, bool> = HashMap::new();
+        for file in self.files.keys() {
+            let Ok(rel) = file.strip_prefix(&resolved) else {
+                continue;
+            };
+            let mut components = rel.components();
+            if let Some(first) = components.next() {
+                let is_dir = components.next().is_some();
+                *entries.entry(path.join(first)).or_default() |= is_dir;
+            }
+        }
+        let links = self
+            .symlinks
+            .iter()
+            .filter(|(link, _)| link.parent() == Some(resolved.as_path()))
+            .filter_map(|(link, target)| {
+                Some(DirEntry {
+                    path: path.join(link.file_name()?),
+                    is_dir: self.is_dir(&self.resolve(target).ok()?),
+                    link: Some(target.clone()),
+                })
+            });
+        Ok(entries
+            .into_iter()
+            .map(|(path, is_dir)| DirEntry {
+                path,
+                is_dir,
+                link: None,
+            })
+            .chain(links)
+            .map(Ok)
+            .collect())
+    }
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        self.record(format!("read: `{}`", &path.display()));
+        self.check_denied(path)?;
+        if let Some(contents) = self.files.get(&self.resolve(path)?) {
+            Ok(contents.to_owned())
+        } else {
+            Err(io::Error::new(io::ErrorKind::NotFound, "File not found"))
+        }
+    }
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.resolve(path)
+    }
 }
 
 impl Default for FakeFileSystem {
@@ -51,16 +289,42 @@ impl Default for FakeFileSystem {
 }
 
 impl FileSystem for FakeFileSystem {
-    fn list_files(&mut self, _path: impl AsRef<Path>) -> Vec<PathBuf> {
-        self.files.keys().cloned().collect()
+    fn dir_reader(&mut self) -> impl DirReader + '_ {
+        self.reader()
+    }
+    fn list_git_files(&mut self, path: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+        self.operations
+            .push(format!("git ls-files: `{}`", path.as_ref().display()));
+        Ok(self.files.keys().cloned().collect())
     }
     fn read_to_string(&mut self, path: &Path) -> io::Result<String> {
-        self.operations.push(format!("read: `{}`", &path.display()));
-        if let Some(contents) = self.files.get(path) {
-            Ok(contents.to_owned())
-        } else {
-            Err(io::Error::new(io::ErrorKind::NotFound, "File not found"))
-        }
+        self.reader().read_to_string(path)
+    }
+    fn canonicalize(&mut self, path: &Path) -> io::Result<PathBuf> {
+        self.reader().canonicalize(path)
+    }
+    fn exists(&mut self, path: &Path) -> bool {
+        let reader = self.reader();
+        reader.symlinks.contains_key(path)
+            || reader
+                .resolve(path)
+                .is_ok_and(|path| reader.files.contains_key(&path) || reader.is_dir(&path))
+    }
+    fn metadata(&mut self, path: &Path) -> io::Result<FileMetadata> {
+        let reader = self.reader();
+        reader.record(format!("metadata: `{}`", &path.display()));
+        reader.check_denied(path)?;
+        let resolved = reader.resolve(path)?;
+        let Some(contents) = reader.files.get(&resolved) else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "File not found"));
+        };
+        Ok(FileMetadata {
+            size: contents.len() as u64,
+            modified: self.modified.get(&resolved).copied(),
+        })
+    }
+    fn count_lines(&mut self, path: &Path) -> io::Result<usize> {
+        Ok(self.read_to_string(path)?.lines().count())
     }
     fn write(&mut self, path: &Path, contents: &str) -> std::result::Result<(), std::io::Error> {
         self.operations
@@ -69,4 +333,10 @@ impl FileSystem for FakeFileSystem {
             .insert(path.to_path_buf(), contents.to_string().clone());
         Ok(())
     }
+    fn write_stdout(&mut self, contents: &str) -> io::Result<()> {
+        self.operations.push("write_stdout".to_string());
+        self.stdout.push_str(contents);
+        self.stdout.push('\n');
+        Ok(())
+    }
 }
@@ -0,0 +1,279 @@
+use crate::core::{adapters::FileSystem, domain::RetCode};
+use colored::Colorize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io, iter,
+    path::{Path, PathBuf},
+};
+
+/// The sidecar of manual descriptions in the repo root, mapping paths to what they hold.
+pub const DESCRIPTIONS: &str = "repo-map.descriptions.toml";
+
+type Extractor = fn(&str) -> Option<String>;
+
+/// The extractor for a file's own description, picked from its extension.
+fn extractor(path: &Path) -> Option<Extractor> {
+    match path.extension()?.to_str()? {
+        "py" | "pyi" => Some(python_docstring),
+        "rs" => Some(rust_inner_doc),
+        "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" | "mts" | "cts" => Some(jsdoc),
+        "md" | "markdown" => Some(markdown_h1),
+        _ => None,
+    }
+}
+
+/// The first line of the file's own description, if it has one in a supported format.
+pub fn extract_annotation(path: &Path, contents: &str) -> Option<String> {
+    extractor(path)?(contents)
+}
+
+/// Reads the annotation of every file in `paths`, relative to `repo_root`, that has one.
+/// Files in unsupported formats aren't read, ones that can't be read are left unannotated.
+pub fn read_annotations(
+    file_sys: &mut impl FileSystem,
+    repo_root: &Path,
+    paths: &[PathBuf],
+) -> HashMap<PathBuf, String> {
+    paths
+        .iter()
+        .filter_map(|path| {
+            let extract = extractor(path)?;
+            let contents = file_sys.read_to_string(&repo_root.join(path)).ok()?;
+            Some((path.clone(), extract(&contents)?))
+        })
+        .collect()
+}
+
+/// Reads the descriptions in the sidecar of the repo root, keyed by their path relative to it.
+/// Descriptions of paths that no longer exist are left out with a warning.
+pub fn read_descriptions(
+    file_sys: &mut impl FileSystem,
+    repo_root: &Path,
+) -> Result<HashMap<PathBuf, String>, RetCode> {
+    #[inline(always)]
+    fn _report(path: &Path, e: impl std::fmt::Display) -> RetCode {
+        eprintln!(
+            "{} {}",
+            format!("Invalid descriptions in `{}`:", path.display())
+                .red()
+                .bold(),
+            e
+        );
+        RetCode::InvalidConfig
+    }
+
+    let path = repo_root.join(DESCRIPTIONS);
+    let contents = match file_sys.read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(_report(&path, e)),
+    };
+    let descriptions: BTreeMap<String, String> =
+        toml::from_str(&contents).map_err(|e| _report(&path, e))?;
+
+    let mut out = HashMap::new();
+    for (described, description) in descriptions {
+        let described = PathBuf::from(described.trim_end_matches('/'));
+        if !file_sys.exists(&repo_root.join(&described)) {
+            eprintln!(
+                "{} `{}` in `{}`",
+                "Description of a path that no longer exists:"
+                    .yellow()
+                    .bold(),
+                described.display(),
+                DESCRIPTIONS
+            );
+            continue;
+        }
+        out.insert(described, description);
+    }
+    Ok(out)
+}
+
+/// The first line of the module docstring, after any comments such as a shebang.
+fn python_docstring(contents: &str) -> Option<String> {
+    let mut lines = contents
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| line.is_empty() || line.starts_with('#'));
+    let first = lines.next()?.trim_start_matches(['r', 'R', 'u', 'U']);
+    let quote = ["\"\"\"", "'''", "\"", "'"]
+        .into_iter()
+        .find(|quote| first.starts_with(quote))?;
+
+    for line in iter::once(&first[quote.len()..]).chain(lines) {
+        let (text, closed) = split_close(line, quote);
+        if !text.is_empty() {
+            return Some(text.to_string());
+        }
+        if closed {
+            return None;
+        }
+    }
+    None
+}
+
+/// The line up to `close`, trimmed, and whether `close` was found.
+fn split_close<'a>(line: &'a str, close: &str) -> (&'a str, bool) {
+    match line.find(close) {
+        Some(end) => (line[..end].trim(), true),
+        None => (line.trim(), false),
+    }
+}
+
+/// The first line of the `//!` comment, after any plain comments such as a license header.
+fn rust_inner_doc(contents: &str) -> Option<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| line.is_empty() || (line.starts_with("//") && !line.starts_with("//!")))
+        .map_while(|line| line.strip_prefix("//!"))
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+}
+
+/// The first line of a leading JSDoc block, skipping tags other than `@file`, `@fileoverview`
+/// and `@module` with a description.
+fn jsdoc(contents: &str) -> Option<String> {
+    let mut lines = contents
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| line.is_empty() || line.starts_with("#!") || line.starts_with("//"));
+    let rest = lines.next()?.strip_prefix("/**")?;
+
+    for line in iter::once(rest).chain(lines) {
+        let (text, closed) = split_close(line, "*/");
+        let text = text.trim_start_matches('*').trim();
+        match text.strip_prefix('@') {
+            None if !text.is_empty() => return Some(text.to_string()),
+            None => {}
+            Some(tag) => {
+                let description = ["fileoverview", "file", "module"]
+                    .into_iter()
+                    .find_map(|name| tag.strip_prefix(name))
+                    .map(str::trim)
+                    .filter(|description| !description.is_empty());
+                if description.is_some() {
+                    return description.map(str::to_string);
+                }
+            }
+        }
+        if closed {
+            return None;
+        }
+    }
+    None
+}
+
+/// The text of the first H1 outside fenced code.
+fn markdown_h1(contents: &str) -> Option<String> {
+    let mut in_fence = false;
+    for line in contents.lines().map(str::trim_end) {
+        if line.starts_with("```") || line.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+        if let Some(title) = line.strip_prefix("# ") {
+            let title = title.trim().trim_end_matches('#').trim();
+            if !title.is_empty() {
+                return Some(title.to_string());
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract_annotation, read_annotations, read_descriptions};
+    use crate::core::{adapters::FakeFileSystem, domain::RetCode};
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+    };
+    use test_case::test_case;
+
+    #[test_case("mod.py", "\"\"\"Readme and gitignore parsing.\"\"\"\n", Some("Readme and gitignore parsing.") ; "Ensure reads a one line docstring")]
+    #[test_case("mod.py", "#!/usr/bin/env python\n# comment\n\nr'''\n\n  Parses things.\n  More detail.\n'''\n", Some("Parses things.") ; "Ensure skips comments and blank docstring lines")]
+    #[test_case("mod.py", "import os\n\"\"\"Not a docstring.\"\"\"\n", None ; "Ensure a string after code is not a docstring")]
+    #[test_case("mod.py", "\"\"\"\n\"\"\"\nx = 'text'\n", None ; "Ensure an empty docstring has no annotation")]
+    #[test_case("lib.rs", "// Licensed under MIT\n\n//! Walks the repo.\n//! In parallel.\nuse std;\n", Some("Walks the repo.") ; "Ensure reads the first inner doc comment")]
+    #[test_case("lib.rs", "/// Outer doc.\nfn main() {}\n", None ; "Ensure outer doc comments are not annotations")]
+    #[test_case("app.ts", "#!/usr/bin/env node\n/**\n * Entry point of the app.\n * @module app\n */\n", Some("Entry point of the app.") ; "Ensure reads a leading JSDoc block")]
+    #[test_case("app.js", "/**\n * @fileoverview Routes requests.\n */\n", Some("Routes requests.") ; "Ensure reads the file overview tag")]
+    #[test_case("app.jsx", "/** @param {string} x */\nexport const f = (x) => x;\n", None ; "Ensure other tags are not annotations")]
+    #[test_case("app.js", "/* Not JSDoc. */\n", None ; "Ensure plain block comments are not annotations")]
+    #[test_case("docs.md", "```shell\n# not a title\n```\n\n# Architecture #\n", Some("Architecture") ; "Ensure reads the first H1 outside code")]
+    #[test_case("data.json", "{\"description\": \"x\"}", None ; "Ensure unsupported formats have no annotation")]
+    fn test_extract_annotation(path: &str, contents: &str, expected_result: Option<&str>) {
+        assert_eq!(
+            extract_annotation(Path::new(path), contents).as_deref(),
+            expected_result
+        );
+    }
+
+    #[test]
+    fn test_read_annotations() {
+        let files = vec![
+            ("repo/src/lib.rs", "//! The library.\n"),
+            ("repo/src/main.rs", "fn main() {}\n"),
+            ("repo/data.json", "{}"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (PathBuf::from(k), v.to_string()))
+        .collect::<HashMap<PathBuf, String>>();
+        let mut file_sys = FakeFileSystem::new(files);
+
+        let paths = vec![
+            PathBuf::from("src/lib.rs"),
+            PathBuf::from("src/main.rs"),
+            PathBuf::from("src/missing.py"),
+            PathBuf::from("data.json"),
+        ];
+        let actual_result = read_annotations(&mut file_sys, Path::new("repo"), &paths);
+
+        assert_eq!(
+            actual_result,
+            HashMap::from([(PathBuf::from("src/lib.rs"), "The library.".to_string())])
+        );
+        assert!(!file_sys
+            .operations
+            .iter()
+            .any(|op| op.contains("data.json")));
+    }
+
+    #[test_case(None, Ok(vec![]) ; "Ensure no sidecar has no descriptions")]
+    #[test_case(Some("\"src/lib.rs\" = \"The library.\"\n\"src/\" = \"The source.\"\n"), Ok(vec![("src/lib.rs", "The library."), ("src", "The source.")]) ; "Ensure reads file and directory descriptions")]
+    #[test_case(Some("\"src/lib.rs\" = \"The library.\"\n\"src/old.rs\" = \"Was removed.\"\n"), Ok(vec![("src/lib.rs", "The library.")]) ; "Ensure skips paths that no longer exist")]
+    #[test_case(Some("src = 1\n"), Err(RetCode::InvalidConfig) ; "Ensure fails on a description that isn't a string")]
+    fn test_read_descriptions(
+        sidecar: Option<&str>,
+        expected_result: Result<Vec<(&str, &str)>, RetCode>,
+    ) {
+        let mut files = HashMap::from([(
+            PathBuf::from("repo/src/lib.rs"),
+            "//! The library.\n".to_string(),
+        )]);
+        if let Some(sidecar) = sidecar {
+            files.insert(
+                PathBuf::from("repo/repo-map.descriptions.toml"),
+                sidecar.to_string(),
+            );
+        }
+        let mut file_sys = FakeFileSystem::new(files);
+
+        let actual_result = read_descriptions(&mut file_sys, Path::new("repo"));
+
+        assert_eq!(
+            actual_result,
+            expected_result.map(|descriptions| descriptions
+                .into_iter()
+                .map(|(path, description)| (PathBuf::from(path), description.to_string()))
+                .collect())
+        );
+    }
+}
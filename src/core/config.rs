@@ -0,0 +1,308 @@
+use crate::core::{
+    adapters::FileSystem,
+    domain::{InsertPosition, MetadataColumn, RenderMode, RetCode, SymlinkMode},
+};
+use colored::Colorize;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+pub const CONFIG_FILENAME: &str = "repo-mapper.toml";
+
+/// The config files searched in the repo root, in order, with the table holding the options.
+const CONFIG_SOURCES: [(&str, &[&str]); 3] = [
+    (CONFIG_FILENAME, &[]),
+    ("pyproject.toml", &["tool", "repo-mapper"]),
+    ("Cargo.toml", &["package", "metadata", "repo-mapper"]),
+];
+
+/// The options that can be set on the command line or in a config file, `None` where unset.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Options {
+    pub readme_path: Option<String>,
+    pub gitignore_path: Option<String>,
+    pub allowed_exts: Option<Vec<String>>,
+    pub ignore_dirs: Option<Vec<String>>,
+    pub ignore_hidden: Option<bool>,
+    pub dirs_only: Option<bool>,
+    pub git_excludes: Option<bool>,
+    pub ignore_files: Option<Vec<String>>,
+    pub git_tracked: Option<bool>,
+    pub symlinks: Option<SymlinkMode>,
+    pub strict: Option<bool>,
+    pub heading: Option<String>,
+    pub heading_level: Option<u8>,
+    pub insert_position: Option<InsertPosition>,
+    pub max_depth: Option<usize>,
+    pub max_children: Option<usize>,
+    pub annotate: Option<bool>,
+    pub metadata: Option<Vec<MetadataColumn>>,
+    pub render: Option<RenderMode>,
+    /// Settings of the named repo map blocks, only read from the config.
+    pub blocks: Option<BTreeMap<String, BlockOptions>>,
+}
+
+impl Options {
+    /// Fills the options unset in `self` from `fallback`.
+    pub fn or(self, fallback: Options) -> Options {
+        Options {
+            readme_path: self.readme_path.or(fallback.readme_path),
+            gitignore_path: self.gitignore_path.or(fallback.gitignore_path),
+            allowed_exts: self.allowed_exts.or(fallback.allowed_exts),
+            ignore_dirs: self.ignore_dirs.or(fallback.ignore_dirs),
+            ignore_hidden: self.ignore_hidden.or(fallback.ignore_hidden),
+            dirs_only: self.dirs_only.or(fallback.dirs_only),
+            git_excludes: self.git_excludes.or(fallback.git_excludes),
+            ignore_files: self.ignore_files.or(fallback.ignore_files),
+            git_tracked: self.git_tracked.or(fallback.git_tracked),
+            symlinks: self.symlinks.or(fallback.symlinks),
+            strict: self.strict.or(fallback.strict),
+            heading: self.heading.or(fallback.heading),
+            heading_level: self.heading_level.or(fallback.heading_level),
+            insert_position: self.insert_position.or(fallback.insert_position),
+            max_depth: self.max_depth.or(fallback.max_depth),
+            max_children: self.max_children.or(fallback.max_children),
+            annotate: self.annotate.or(fallback.annotate),
+            metadata: self.metadata.or(fallback.metadata),
+            render: self.render.or(fallback.render),
+            blocks: self.blocks.or(fallback.blocks),
+        }
+    }
+}
+
+/// The settings of one repo map block, unset ones fall back to the global options. The ignore
+/// settings aren't among them, they decide what the walk enters so they apply to every block.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BlockOptions {
+    /// Subdirectory of the repo root to map.
+    pub root: Option<String>,
+    pub allowed_exts: Option<Vec<String>>,
+    pub dirs_only: Option<bool>,
+    pub max_depth: Option<usize>,
+    pub max_children: Option<usize>,
+}
+
+impl BlockOptions {
+    /// Fills the options unset in `self` from `fallback`.
+    pub fn or(self, fallback: BlockOptions) -> BlockOptions {
+        BlockOptions {
+            root: self.root.or(fallback.root),
+            allowed_exts: self.allowed_exts.or(fallback.allowed_exts),
+            dirs_only: self.dirs_only.or(fallback.dirs_only),
+            max_depth: self.max_depth.or(fallback.max_depth),
+            max_children: self.max_children.or(fallback.max_children),
+        }
+    }
+
+    /// The root relative to the repo root, without `./` or a trailing `/`. `..` and absolute
+    /// paths are rejected as they would map outside the repo.
+    pub fn root(&self) -> Result<PathBuf, String> {
+        let root = self.root.as_deref().unwrap_or_default();
+        let mut normalized = PathBuf::new();
+        for component in Path::new(root).components() {
+            match component {
+                Component::Normal(part) => normalized.push(part),
+                Component::CurDir => {}
+                _ => return Err(format!("`{root}` is not below the repo root")),
+            }
+        }
+        Ok(normalized)
+    }
+}
+
+/// Reads the options from the first config in `repo_root`: `repo-mapper.toml`, then
+/// `[tool.repo-mapper]` in `pyproject.toml`, then `[package.metadata.repo-mapper]` in
+/// `Cargo.toml`. Paths in the config are relative to `repo_root`.
+pub fn parse_config(file_sys: &mut impl FileSystem, repo_root: &Path) -> Result<Options, RetCode> {
+    #[inline(always)]
+    fn _report(path: &Path, e: impl std::fmt::Display) -> RetCode {
+        eprintln!(
+            "{} {}",
+            format!("Invalid config in `{}`:", path.display())
+                .red()
+                .bold(),
+            e
+        );
+        RetCode::InvalidConfig
+    }
+
+    for (filename, keys) in CONFIG_SOURCES {
+        let path = repo_root.join(filename);
+        let contents = match file_sys.read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(_report(&path, e)),
+        };
+        let config: toml::Value = toml::from_str(&contents).map_err(|e| _report(&path, e))?;
+        let Some(table) = keys.iter().try_fold(&config, |value, key| value.get(key)) else {
+            continue;
+        };
+
+        let options: Options = table.clone().try_into().map_err(|e| _report(&path, e))?;
+        let _resolve = |path: Option<String>| {
+            path.map(|path| repo_root.join(path).to_string_lossy().to_string())
+        };
+        return Ok(Options {
+            readme_path: _resolve(options.readme_path),
+            gitignore_path: _resolve(options.gitignore_path),
+            ..options
+        });
+    }
+    Ok(Options::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_config, BlockOptions, Options};
+    use crate::core::{
+        adapters::FakeFileSystem,
+        converters::to_strings,
+        domain::{InsertPosition, MetadataColumn, RetCode, SymlinkMode},
+    };
+    use std::{
+        collections::{BTreeMap, HashMap},
+        path::{Path, PathBuf},
+    };
+    use test_case::test_case;
+
+    #[test_case(
+        vec![],
+        Ok(Options::default()) ;
+        "Ensure no config gives no options"
+    )]
+    #[test_case(
+        vec![("repo/repo-mapper.toml", "allowed-exts = [\"rs\"]\nsymlinks = \"follow\"\n")],
+        Ok(Options { allowed_exts: Some(to_strings(["rs"])), symlinks: Some(SymlinkMode::Follow), ..Options::default() }) ;
+        "Ensure reads repo-mapper.toml"
+    )]
+    #[test_case(
+        vec![("repo/pyproject.toml", "[project]\nname = \"x\"\n\n[tool.repo-mapper]\nreadme-path = \"docs/README.md\"\nignore-hidden = true\n")],
+        Ok(Options { readme_path: Some("repo/docs/README.md".to_string()), ignore_hidden: Some(true), ..Options::default() }) ;
+        "Ensure reads pyproject.toml relative to the repo root"
+    )]
+    #[test_case(
+        vec![("repo/Cargo.toml", "[package]\nname = \"x\"\n\n[package.metadata.repo-mapper]\ndirs-only = true\n")],
+        Ok(Options { dirs_only: Some(true), ..Options::default() }) ;
+        "Ensure reads Cargo.toml"
+    )]
+    #[test_case(
+        vec![
+            ("repo/pyproject.toml", "[project]\nname = \"x\"\n"),
+            ("repo/Cargo.toml", "[package.metadata.repo-mapper]\ndirs-only = true\n"),
+        ],
+        Ok(Options { dirs_only: Some(true), ..Options::default() }) ;
+        "Ensure skips files without a repo-mapper table"
+    )]
+    #[test_case(
+        vec![
+            ("repo/repo-mapper.toml", "strict = true\n"),
+            ("repo/Cargo.toml", "[package.metadata.repo-mapper]\ndirs-only = true\n"),
+        ],
+        Ok(Options { strict: Some(true), ..Options::default() }) ;
+        "Ensure only the first config is used"
+    )]
+    #[test_case(
+        vec![("repo/repo-mapper.toml", "[blocks.src]\nroot = \"src\"\n\n[blocks.overview]\ndirs-only = true\n")],
+        Ok(Options {
+            blocks: Some(BTreeMap::from([
+                ("src".to_string(), BlockOptions { root: Some("src".to_string()), ..BlockOptions::default() }),
+                ("overview".to_string(), BlockOptions { dirs_only: Some(true), ..BlockOptions::default() }),
+            ])),
+            ..Options::default()
+        }) ;
+        "Ensure reads block settings"
+    )]
+    #[test_case(
+        vec![("repo/repo-mapper.toml", "heading = \"Project layout\"\nheading-level = 2\ninsert-position = \"after:Installation\"\n")],
+        Ok(Options {
+            heading: Some("Project layout".to_string()),
+            heading_level: Some(2),
+            insert_position: Some(InsertPosition::After("Installation".to_string())),
+            ..Options::default()
+        }) ;
+        "Ensure reads the heading and insert position"
+    )]
+    #[test_case(
+        vec![("repo/repo-mapper.toml", "metadata = [\"size\", \"mtime\"]\n")],
+        Ok(Options { metadata: Some(vec![MetadataColumn::Size, MetadataColumn::Modified]), ..Options::default() }) ;
+        "Ensure reads the metadata columns"
+    )]
+    #[test_case(
+        vec![("repo/repo-mapper.toml", "insert-position = \"middle\"\n")],
+        Err(RetCode::InvalidConfig) ;
+        "Ensure invalid insert positions are rejected"
+    )]
+    #[test_case(
+        vec![("repo/repo-mapper.toml", "allowed_exts = [\"rs\"]\n")],
+        Err(RetCode::InvalidConfig) ;
+        "Ensure unknown keys are rejected"
+    )]
+    #[test_case(
+        vec![("repo/repo-mapper.toml", "[blocks.src]\nignore-hidden = true\n")],
+        Err(RetCode::InvalidConfig) ;
+        "Ensure global settings are rejected in blocks"
+    )]
+    #[test_case(
+        vec![("repo/repo-mapper.toml", "symlinks = \"sometimes\"\n")],
+        Err(RetCode::InvalidConfig) ;
+        "Ensure invalid values are rejected"
+    )]
+    fn test_parse_config(files: Vec<(&str, &str)>, expected_result: Result<Options, RetCode>) {
+        let mut file_sys = FakeFileSystem::new(
+            files
+                .into_iter()
+                .map(|(k, v)| (PathBuf::from(k), v.to_string()))
+                .collect::<HashMap<PathBuf, String>>(),
+        );
+
+        assert_eq!(
+            parse_config(&mut file_sys, Path::new("repo")),
+            expected_result
+        );
+    }
+
+    #[test]
+    fn test_options_or() {
+        let cli = Options {
+            allowed_exts: Some(to_strings(["py"])),
+            ignore_hidden: Some(true),
+            ..Options::default()
+        };
+        let config = Options {
+            allowed_exts: Some(to_strings(["rs"])),
+            dirs_only: Some(true),
+            ..Options::default()
+        };
+
+        let expected_result = Options {
+            allowed_exts: Some(to_strings(["py"])),
+            ignore_hidden: Some(true),
+            dirs_only: Some(true),
+            ..Options::default()
+        };
+
+        assert_eq!(cli.or(config), expected_result);
+    }
+
+    #[test_case(None, Ok("") ; "Ensure no root is the repo root")]
+    #[test_case(Some("./docs/"), Ok("docs") ; "Ensure strips the leading dot and trailing slash")]
+    #[test_case(Some("docs/./api"), Ok("docs/api") ; "Ensure strips inner dots")]
+    #[test_case(Some("../docs"), Err(()) ; "Ensure rejects parent directories")]
+    #[test_case(Some("/docs"), Err(()) ; "Ensure rejects absolute paths")]
+    fn test_block_root(root: Option<&str>, expected_result: Result<&str, ()>) {
+        let options = BlockOptions {
+            root: root.map(str::to_string),
+            ..BlockOptions::default()
+        };
+
+        assert_eq!(
+            options.root().map_err(|_| ()),
+            expected_result.map(PathBuf::from)
+        );
+    }
+}
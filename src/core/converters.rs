@@ -2,6 +2,8 @@ use std::{collections::HashSet, path::PathBuf};
 
 use regex::Regex;
 
+use crate::core::{domain::IgnoreRules, parsing::parse_pattern};
+
 pub fn to_pathbufs<I, S>(inp: I) -> Vec<PathBuf>
 where
     I: IntoIterator<Item = S>,
@@ -37,3 +39,15 @@ where
         .filter_map(|s| Regex::new(s.as_ref()).ok())
         .collect::<Vec<Regex>>()
 }
+
+pub fn to_ignore_rules<I, S>(inp: I) -> IgnoreRules
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    IgnoreRules::new(
+        inp.into_iter()
+            .filter_map(|s| parse_pattern(s.as_ref()).ok().flatten())
+            .collect(),
+    )
+}
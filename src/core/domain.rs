@@ -1,10 +1,13 @@
 use itertools::Itertools;
 use rayon::prelude::*;
 use regex::Regex;
+use serde::Deserialize;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     ffi,
     path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 #[derive(Debug, PartialEq, Eq)]
@@ -13,61 +16,622 @@ pub enum RetCode {
     ModifiedReadme,
     FailedParsingFile,
     FailedToWriteReadme,
+    /// A `--gitignore-path` not named `.gitignore`, or no README path without `--stdout`.
+    /// The README itself can have any name.
     InvalidFilename,
+    FailedListingGitFiles,
+    IncompleteWalk,
+    OutdatedReadme,
+    InvalidConfig,
+}
+
+impl RetCode {
+    /// The exit code listed in the README.
+    pub fn code(&self) -> u8 {
+        match self {
+            RetCode::NoModification => 0,
+            RetCode::ModifiedReadme => 1,
+            RetCode::FailedParsingFile => 2,
+            RetCode::FailedToWriteReadme => 3,
+            RetCode::InvalidFilename => 4,
+            RetCode::FailedListingGitFiles => 5,
+            RetCode::IncompleteWalk => 6,
+            RetCode::OutdatedReadme => 7,
+            RetCode::InvalidConfig => 8,
+        }
+    }
+}
+
+/// How the walk treats symlinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkMode {
+    /// Leaves symlinks out of the map.
+    Skip,
+    /// Maps symlinks as leaves like any other file, without entering them.
+    #[default]
+    Plain,
+    /// Maps symlinks as leaves annotated with their target, without entering them.
+    List,
+    /// Maps symlinks as what they point to, entering linked directories unless that loops.
+    Follow,
+}
+
+impl FromStr for SymlinkMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "plain" => Ok(Self::Plain),
+            "list" => Ok(Self::List),
+            "follow" => Ok(Self::Follow),
+            _ => Err(format!(
+                "Invalid symlink mode `{s}`, expected one of `skip`, `plain`, `list` or `follow`"
+            )),
+        }
+    }
+}
+
+/// Where a README without a repo map gets one.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum InsertPosition {
+    /// At the end of the README.
+    #[default]
+    End,
+    /// At the start of the README.
+    Start,
+    /// At the end of the section under the heading with this text.
+    After(String),
+}
+
+impl FromStr for InsertPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "end" => Ok(Self::End),
+            "start" => Ok(Self::Start),
+            _ => match s.strip_prefix("after:") {
+                Some(heading) if !heading.trim().is_empty() => {
+                    Ok(Self::After(heading.trim().to_string()))
+                }
+                _ => Err(format!(
+                    "Invalid insert position `{s}`, expected one of `end`, `start` or `after:<heading>`"
+                )),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for InsertPosition {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// A column of file metadata rendered after the entries, directories showing the totals of
+/// the files below them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataColumn {
+    /// The size in bytes, e.g. `1.2 KiB`.
+    Size,
+    /// The number of lines, e.g. `40 lines`.
+    Lines,
+    /// The date of the last modification in UTC, the latest one for directories.
+    #[serde(rename = "mtime")]
+    Modified,
+}
+
+impl FromStr for MetadataColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "size" => Ok(Self::Size),
+            "lines" => Ok(Self::Lines),
+            "mtime" => Ok(Self::Modified),
+            _ => Err(format!(
+                "Invalid metadata column `{s}`, expected one of `size`, `lines` or `mtime`"
+            )),
+        }
+    }
+}
+
+/// The metadata of a file, or the totals of the files below a directory. Columns that weren't
+/// gathered are `None`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FileStats {
+    pub size: Option<u64>,
+    pub lines: Option<usize>,
+    pub modified: Option<SystemTime>,
+}
+
+impl FileStats {
+    /// Adds `other` to the totals, keeping the latest modification.
+    fn add(&mut self, other: &FileStats) {
+        #[inline(always)]
+        fn _combine<T>(a: Option<T>, b: Option<T>, f: impl Fn(T, T) -> T) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(f(a, b)),
+                (a, b) => a.or(b),
+            }
+        }
+
+        self.size = _combine(self.size, other.size, |a, b| a + b);
+        self.lines = _combine(self.lines, other.lines, |a, b| a + b);
+        self.modified = _combine(self.modified, other.modified, SystemTime::max);
+    }
+
+    /// The rendered size, line count and modification date, in that order.
+    fn cells(&self) -> [Option<String>; 3] {
+        [
+            self.size.map(format_size),
+            self.lines.map(|lines| match lines {
+                1 => "1 line".to_string(),
+                lines => format!("{lines} lines"),
+            }),
+            self.modified.and_then(format_date),
+        ]
+    }
+}
+
+/// Formats a size in bytes with binary units, e.g. `512 B` or `1.2 KiB`.
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{size} B");
+    }
+    let mut size = size as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// Formats the UTC date of `time` as `YYYY-MM-DD`, `None` before the epoch.
+fn format_date(time: SystemTime) -> Option<String> {
+    let days = (time.duration_since(UNIX_EPOCH).ok()?.as_secs() / 86_400) as i64;
+    // Howard Hinnant's days_from_civil inverted, counting eras of 400 years from 0000-03-01
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    Some(format!("{year:04}-{month:02}-{day:02}"))
+}
+
+/// How a tree is rendered in Markdown, the clickable modes link every entry to its path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderMode {
+    /// Lines of a tree in a fenced code block.
+    #[default]
+    Code,
+    /// A nested list of links.
+    List,
+    /// Lines of a tree in a `<pre>` block, with the names as `<a href>` links.
+    Html,
+}
+
+impl FromStr for RenderMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "code" => Ok(Self::Code),
+            "list" => Ok(Self::List),
+            "html" => Ok(Self::Html),
+            _ => Err(format!(
+                "Invalid render mode `{s}`, expected one of `code`, `list` or `html`"
+            )),
+        }
+    }
+}
+
+/// Limits on how much of a tree is rendered, `None` for no limit.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RenderLimits {
+    /// How many levels of entries are rendered, deeper ones are left out. At 0 only a summary
+    /// of the root's entries is rendered.
+    pub max_depth: Option<usize>,
+    /// How many entries of a directory are rendered, the rest collapse into a summary line.
+    pub max_children: Option<usize>,
 }
 
 #[derive(Debug)]
 pub struct FileTree {
     pub nodes: HashMap<String, FileTree>,
+    /// The target of a symlink mapped as a leaf.
+    pub link: Option<PathBuf>,
+    /// Whether the node is a directory, even if mapped as a leaf.
+    pub is_dir: bool,
+    /// A description rendered as a trailing comment.
+    pub annotation: Option<String>,
+    /// The metadata of a file, or the totals of the mapped files below a directory.
+    pub stats: FileStats,
 }
 
 impl FileTree {
     pub fn new() -> Self {
         FileTree {
             nodes: HashMap::new(),
+            link: None,
+            is_dir: false,
+            annotation: None,
+            stats: FileStats::default(),
         }
     }
 
-    fn insert(&mut self, path: &Path) {
+    fn insert(&mut self, path: &Path, is_dir: bool) {
         let parts = path
             .components()
             .map(|c| c.as_os_str().to_string_lossy().to_string());
 
         let mut node = self;
         for part in parts {
+            node.is_dir = true;
             node = node.nodes.entry(part).or_default();
         }
+        node.is_dir |= is_dir;
     }
 
     pub fn create_map(mut self, paths: Vec<PathBuf>) -> Self {
         for path in paths {
-            self.insert(&path);
+            self.insert(&path, false);
+        }
+        self
+    }
+
+    /// Like `create_map`, for paths that are all directories.
+    pub fn create_dir_map(mut self, paths: Vec<PathBuf>) -> Self {
+        for path in paths {
+            self.insert(&path, true);
+        }
+        self
+    }
+
+    /// Annotates the mapped entries in `links` with their targets, unmapped ones are skipped.
+    pub fn add_links(mut self, links: &HashMap<PathBuf, PathBuf>) -> Self {
+        for (path, target) in links {
+            let node = path.components().try_fold(&mut self, |node, c| {
+                node.nodes.get_mut(c.as_os_str().to_string_lossy().as_ref())
+            });
+            if let Some(node) = node {
+                node.link = Some(target.clone());
+            }
+        }
+        self
+    }
+
+    /// Sets the annotations of the mapped entries in `annotations`, unmapped ones are skipped.
+    pub fn add_annotations(mut self, annotations: &HashMap<PathBuf, String>) -> Self {
+        for (path, annotation) in annotations {
+            let node = path.components().try_fold(&mut self, |node, c| {
+                node.nodes.get_mut(c.as_os_str().to_string_lossy().as_ref())
+            });
+            if let Some(node) = node {
+                node.annotation = Some(annotation.clone());
+            }
+        }
+        self
+    }
+
+    /// Adds the stats of the files in `stats` to their entries and to the totals of every
+    /// mapped directory above them, so directories mapped without their files still get them.
+    pub fn add_stats(mut self, stats: &HashMap<PathBuf, FileStats>) -> Self {
+        for (path, file_stats) in stats {
+            let mut node = &mut self;
+            for c in path.components() {
+                let Some(next) = node.nodes.get_mut(c.as_os_str().to_string_lossy().as_ref())
+                else {
+                    break;
+                };
+                next.stats.add(file_stats);
+                node = next;
+            }
         }
         self
     }
 
     pub fn render(&self) -> String {
-        fn _walk(tree: &HashMap<String, FileTree>, prefix: String, out: &mut Vec<String>) {
+        self.render_block("", RenderLimits::default())
+    }
+
+    /// Renders the tree as a repo map block, `info` is the fence's info string.
+    pub fn render_block(&self, info: &str, limits: RenderLimits) -> String {
+        format!(
+            "# Repo map\n```{info}\n{}\n::\n```",
+            self.render_tree(limits)
+        )
+    }
+
+    /// The lines of the tree in the order they are rendered, cut to `limits`.
+    fn rows(&self, limits: RenderLimits) -> Vec<Row<'_>> {
+        fn _walk<'a>(
+            tree: &'a HashMap<String, FileTree>,
+            parent: &Path,
+            prefix: String,
+            depth: usize,
+            limits: RenderLimits,
+            out: &mut Vec<Row<'a>>,
+        ) {
             let mut items: Vec<_> = tree.iter().collect();
 
             items.sort_by_key(|(name, node)| (node.nodes.is_empty(), name.to_owned()));
 
-            for (i, (name, _)) in items.iter().enumerate() {
-                let is_last = i == items.len() - 1;
+            // past the max depth, which is only reached at 0, entries are all summarised
+            let max_children = match limits.max_depth {
+                Some(max_depth) if depth > max_depth => Some(0),
+                _ => limits.max_children,
+            };
+            let hidden = match max_children {
+                Some(max_children) if items.len() > max_children => items.split_off(max_children),
+                _ => Vec::new(),
+            };
+
+            for (i, (name, node)) in items.iter().enumerate() {
+                let is_last = i == items.len() - 1 && hidden.is_empty();
                 let connector = if is_last { "└── " } else { "├── " };
-                out.push(format!("{prefix}{connector}{name}"));
+                let path = parent.join(name);
+                out.push(Row {
+                    prefix: format!("{prefix}{connector}"),
+                    depth,
+                    name: name.to_string(),
+                    path: Some(path.clone()),
+                    link: node.link.as_deref(),
+                    annotation: node.annotation.as_deref(),
+                    stats: node.stats,
+                });
 
-                if let Some(subtree) = tree.get(*name) {
-                    let new_prefix = format!("{prefix}{}", if is_last { "    " } else { "│   " });
-                    _walk(&subtree.nodes, new_prefix, out);
+                if limits.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+                    continue;
                 }
+                let new_prefix = format!("{prefix}{}", if is_last { "    " } else { "│   " });
+                _walk(&node.nodes, &path, new_prefix, depth + 1, limits, out);
+            }
+
+            if !hidden.is_empty() {
+                let mut stats = FileStats::default();
+                for (_, node) in &hidden {
+                    stats.add(&node.stats);
+                }
+                out.push(Row {
+                    prefix: format!("{prefix}└── "),
+                    depth,
+                    name: format!("… {}", summarize(&hidden)),
+                    path: None,
+                    link: None,
+                    annotation: None,
+                    stats,
+                });
             }
         }
 
         let mut out = Vec::new();
-        _walk(&self.nodes, String::new(), &mut out);
-        format!("# Repo map\n```\n{}\n::\n```", out.join("\n"))
+        _walk(
+            &self.nodes,
+            Path::new(""),
+            String::new(),
+            1,
+            limits,
+            &mut out,
+        );
+        out
+    }
+
+    /// Renders the tree's lines alone, without the surrounding block. Metadata is aligned in
+    /// columns, followed by annotations as trailing comments.
+    pub fn render_tree(&self, limits: RenderLimits) -> String {
+        self.render_columns(limits, None)
+    }
+
+    /// Like `render_tree`, as HTML for a `<pre>` block with every entry linked to its path
+    /// below `base`.
+    pub fn render_html(&self, limits: RenderLimits, base: &str) -> String {
+        self.render_columns(limits, Some(base))
+    }
+
+    /// Lays out the lines with their metadata and annotations aligned, as linked HTML if
+    /// `base` is given.
+    fn render_columns(&self, limits: RenderLimits, base: Option<&str>) -> String {
+        let rows = self.rows(limits);
+        let _escape = |text: &str| match base {
+            Some(_) => escape_html(text),
+            None => text.to_string(),
+        };
+
+        let cells: Vec<[Option<String>; 3]> = rows.iter().map(|row| row.stats.cells()).collect();
+        // a column is only rendered if some entry has it, as wide as its widest cell
+        let column_widths: Vec<(usize, usize)> = (0..3)
+            .filter_map(|i| {
+                cells
+                    .iter()
+                    .filter_map(|row| row[i].as_ref().map(|cell| cell.chars().count()))
+                    .max()
+                    .map(|width| (i, width))
+            })
+            .collect();
+        // the width of what is shown, without the markup of links
+        let texts: Vec<String> = rows
+            .iter()
+            .map(|row| format!("{}{}{}", row.prefix, row.name, row.link_suffix()))
+            .collect();
+        let width = rows
+            .iter()
+            .zip(&texts)
+            .filter(|(row, _)| !column_widths.is_empty() || row.annotation.is_some())
+            .map(|(_, text)| text.chars().count())
+            .max()
+            .unwrap_or_default();
+
+        rows.iter()
+            .zip(texts)
+            .zip(cells)
+            .map(|((row, text), row_cells)| {
+                let mut line = match (base, &row.path) {
+                    (Some(base), Some(path)) => format!(
+                        "{}<a href=\"{}\">{}</a>{}",
+                        row.prefix,
+                        href(base, path),
+                        escape_html(&row.name),
+                        escape_html(&row.link_suffix())
+                    ),
+                    _ => _escape(&text),
+                };
+                if column_widths.is_empty() && row.annotation.is_none() {
+                    return line;
+                }
+                line.push_str(&" ".repeat(width - text.chars().count()));
+                for (i, column_width) in &column_widths {
+                    let cell = row_cells[*i].as_deref().unwrap_or_default();
+                    line.push_str(&format!("  {cell:>column_width$}"));
+                }
+                if let Some(annotation) = row.annotation {
+                    line.push_str(&format!("  # {}", _escape(annotation)));
+                }
+                line.trim_end().to_string()
+            })
+            .join("\n")
     }
+
+    /// Renders the tree as a nested Markdown list, every entry linked to its path below
+    /// `base`. Metadata and annotations follow the links.
+    pub fn render_list(&self, limits: RenderLimits, base: &str) -> String {
+        self.rows(limits)
+            .into_iter()
+            .map(|row| {
+                let indent = "  ".repeat(row.depth - 1);
+                let mut line = match &row.path {
+                    Some(path) => format!(
+                        "{indent}- [{}]({}){}",
+                        escape_markdown(&row.name),
+                        href(base, path),
+                        escape_markdown(&row.link_suffix())
+                    ),
+                    None => format!("{indent}- {}", row.name),
+                };
+                let cells: Vec<String> = row.stats.cells().into_iter().flatten().collect();
+                if !cells.is_empty() {
+                    line.push_str(&format!(" ({})", cells.join(", ")));
+                }
+                if let Some(annotation) = row.annotation {
+                    line.push_str(&format!(" — {}", escape_markdown(annotation)));
+                }
+                line
+            })
+            .join("\n")
+    }
+}
+
+/// One line of a rendered tree, before it is laid out.
+struct Row<'a> {
+    /// The tree lines in front of the name, e.g. `│   ├── `.
+    prefix: String,
+    depth: usize,
+    /// The entry's name, or the summary of the entries collapsed into this line.
+    name: String,
+    /// The entry's path below the tree's root, `None` for a summary.
+    path: Option<PathBuf>,
+    link: Option<&'a Path>,
+    annotation: Option<&'a str>,
+    stats: FileStats,
+}
+
+impl Row<'_> {
+    /// The target of a symlink mapped as a leaf, e.g. ` -> ../shared`.
+    fn link_suffix(&self) -> String {
+        self.link
+            .map(|target| format!(" -> {}", target.display()))
+            .unwrap_or_default()
+    }
+}
+
+/// The relative URL of `path` below `base`, percent-encoding anything but unreserved
+/// characters in each part.
+fn href(base: &str, path: &Path) -> String {
+    base.split('/')
+        .map(str::to_string)
+        .chain(
+            path.components()
+                .map(|c| c.as_os_str().to_string_lossy().to_string()),
+        )
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            part.bytes()
+                .map(|b| match b {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                        (b as char).to_string()
+                    }
+                    _ => format!("%{b:02X}"),
+                })
+                .collect::<String>()
+        })
+        .join("/")
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Backslash-escapes the characters that could start Markdown emphasis, code or links.
+fn escape_markdown(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' => vec!['\\', c],
+            _ => vec![c],
+        })
+        .collect()
+}
+
+/// Counts collapsed entries, e.g. `240 more files (212 .py, 28 .json)`.
+fn summarize(hidden: &[(&String, &FileTree)]) -> String {
+    #[inline(always)]
+    fn _plural(count: usize, singular: &str, plural: &str) -> String {
+        format!(
+            "{count} more {}",
+            if count == 1 { singular } else { plural }
+        )
+    }
+
+    let dirs = hidden.iter().filter(|(_, node)| node.is_dir).count();
+    let mut exts: BTreeMap<String, usize> = BTreeMap::new();
+    for (name, _) in hidden.iter().filter(|(_, node)| !node.is_dir) {
+        let ext = Path::new(name.as_str())
+            .extension()
+            .map_or("other".to_string(), |ext| {
+                format!(".{}", ext.to_string_lossy())
+            });
+        *exts.entry(ext).or_default() += 1;
+    }
+    let files: usize = exts.values().sum();
+
+    let mut parts = Vec::new();
+    if dirs > 0 {
+        parts.push(_plural(dirs, "directory", "directories"));
+    }
+    if files > 0 {
+        let exts = exts
+            .into_iter()
+            .sorted_by_key(|(ext, count)| (std::cmp::Reverse(*count), ext.clone()))
+            .map(|(ext, count)| format!("{count} {ext}"))
+            .join(", ");
+        parts.push(format!("{} ({exts})", _plural(files, "file", "files")));
+    }
+    parts.join(", ")
 }
 
 impl Default for FileTree {
@@ -76,12 +640,106 @@ impl Default for FileTree {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct IgnorePattern {
+    pub regex: Regex,
+    pub negated: bool,
+    pub dir_only: bool,
+    /// Directory of the ignore file relative to the repo root, patterns only apply below it.
+    pub base: PathBuf,
+}
+
+impl IgnorePattern {
+    pub fn is_match(&self, path: &Path, is_dir: bool) -> bool {
+        if !is_dir && self.dir_only {
+            return false;
+        }
+        match path.strip_prefix(&self.base) {
+            Ok(rel) if !rel.as_os_str().is_empty() => self.regex.is_match(&rel.to_string_lossy()),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct IgnoreRules {
+    pub patterns: Vec<IgnorePattern>,
+}
+
+impl IgnoreRules {
+    pub fn new(patterns: Vec<IgnorePattern>) -> Self {
+        Self { patterns }
+    }
+
+    /// Appends rules read from an ignore file in `base`, later rules take precedence.
+    pub fn extend(&mut self, base: &Path, rules: IgnoreRules) {
+        self.patterns
+            .extend(rules.patterns.into_iter().map(|pattern| IgnorePattern {
+                base: base.to_path_buf(),
+                ..pattern
+            }));
+    }
+
+    /// The last matching pattern decides, `None` if no pattern matches the path.
+    fn matched(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.is_match(path, is_dir))
+            .map(|pattern| !pattern.negated)
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        // git never descends into an excluded directory, so nothing below it can be re-included
+        let parent_excluded = path
+            .ancestors()
+            .skip(1)
+            .filter(|anc| !anc.as_os_str().is_empty())
+            .any(|anc| self.matched(anc, true) == Some(true));
+
+        parent_excluded || self.matched(path, is_dir).unwrap_or(false)
+    }
+}
+
+/// The rules of every ignore file with one name, e.g. all the `.gitignore` files in the repo.
+#[derive(Debug)]
+pub struct IgnoreLayer {
+    pub filename: String,
+    pub rules: IgnoreRules,
+    /// An ignore file already read into `rules`, so it isn't applied twice.
+    pub loaded: Option<PathBuf>,
+    /// Whether files with this name in subdirectories apply too, like nested `.gitignore`s.
+    pub nested: bool,
+}
+
+impl IgnoreLayer {
+    pub fn new(filename: impl Into<String>) -> Self {
+        Self {
+            filename: filename.into(),
+            rules: IgnoreRules::default(),
+            loaded: None,
+            nested: true,
+        }
+    }
+
+    /// A layer only read from the repo root, as Docker and npm read their ignore files.
+    pub fn root_only(filename: impl Into<String>) -> Self {
+        Self {
+            nested: false,
+            ..Self::new(filename)
+        }
+    }
+}
+
+/// Keeps the paths not filtered out, relative to `root`. Symlinks mapped as leaves, keyed
+/// relative to `root` in `links`, have no extension of their own so they are always allowed.
 pub fn filter_paths(
     paths: Vec<PathBuf>,
     root: &PathBuf,
     allowed_exts: &HashSet<String>,
+    links: &HashMap<PathBuf, PathBuf>,
     ignore_dirs: &HashSet<String>,
-    gitignored_patterns: &[Regex],
+    ignore_rules: &[IgnoreRules],
     ignore_hidden: bool,
 ) -> Vec<PathBuf> {
     #[inline(always)]
@@ -119,19 +777,13 @@ pub fn filter_paths(
         }
     }
 
-    #[inline(always)]
-    fn _is_gitignored(path: &Path, patterns: &[Regex]) -> bool {
-        let rel_str = path.to_string_lossy();
-        patterns.iter().any(|re| re.is_match(&rel_str))
-    }
-
     paths
         .into_par_iter()
         .filter(|e| !ignore_hidden || !_is_hidden(e))
-        .filter(|e| _is_allowed_ext(e, allowed_exts))
         .filter(|e| !_is_ignored_dir(e, root, ignore_dirs))
         .filter_map(|e| e.as_path().strip_prefix(root).ok().map(|p| p.to_owned()))
-        .filter(|p| !_is_gitignored(p, gitignored_patterns))
+        .filter(|p| links.contains_key(p) || _is_allowed_ext(p, allowed_exts))
+        .filter(|p| !ignore_rules.iter().any(|rules| rules.is_ignored(p, false)))
         .collect()
 }
 
@@ -146,10 +798,17 @@ pub fn filter_dirnames(paths: Vec<PathBuf>) -> Vec<PathBuf> {
 
 #[cfg(test)]
 mod tests {
-    use super::{filter_dirnames, filter_paths, FileTree};
-    use crate::core::converters::{to_hashset, to_pathbufs, to_regex_vec};
+    use super::{
+        filter_dirnames, filter_paths, format_date, format_size, href, FileStats, FileTree,
+        IgnoreRules, RenderLimits,
+    };
+    use crate::core::converters::{to_hashset, to_ignore_rules, to_pathbufs};
     use crate::core::test_utils::get_mock_repo_vec;
-    use std::path::PathBuf;
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+        time::{Duration, UNIX_EPOCH},
+    };
     use test_case::test_case;
 
     #[test]
@@ -159,8 +818,7 @@ mod tests {
         let root = PathBuf::from("user/root/repo");
         let allowed_exts = to_hashset(vec!["py", "rs", "toml"]);
         let ignore_dirs = to_hashset(Vec::<&str>::new());
-        let gitignored_patterns =
-            to_regex_vec(vec![r"(^|/)\.pytest_cache/(.*)?$", r"(^|/)target/(.*)?$"]);
+        let gitignore = to_ignore_rules(vec![".pytest_cache/", "target/"]);
 
         let expected_result: Vec<PathBuf> = to_pathbufs(vec![
             "src/core/some_file.rs",
@@ -175,8 +833,9 @@ mod tests {
             paths,
             &root,
             &allowed_exts,
+            &HashMap::new(),
             &ignore_dirs,
-            &gitignored_patterns,
+            &[gitignore],
             true,
         );
 
@@ -190,8 +849,7 @@ mod tests {
         let root = PathBuf::from("user/root/repo");
         let allowed_exts = to_hashset(Vec::<&str>::new());
         let ignore_dirs = to_hashset(vec!["scrap", ".venv"]);
-        let gitignored_patterns =
-            to_regex_vec(vec![r"(^|/)\.pytest_cache/(.*)?$", r"(^|/)target/(.*)?$"]);
+        let gitignore = to_ignore_rules(vec![".pytest_cache/", "target/"]);
 
         let expected_result: Vec<PathBuf> = to_pathbufs(vec![
             ".github/workflows/ci.yaml",
@@ -207,8 +865,68 @@ mod tests {
             paths,
             &root,
             &allowed_exts,
+            &HashMap::new(),
             &ignore_dirs,
-            &gitignored_patterns,
+            &[gitignore],
+            false,
+        );
+
+        assert_eq!(actual_result, expected_result);
+    }
+
+    #[test_case("build/keep.md", vec!["build/", "!build/keep.md"], true ; "Ensure cannot re-include a file if its parent directory is excluded")]
+    #[test_case("build/keep.md", vec!["build/*", "!build/keep.md"], false ; "Ensure negation re-includes a file")]
+    #[test_case("build/sub/keep.md", vec!["build/*", "!build/sub/keep.md"], true ; "Ensure cannot re-include below an excluded subdirectory")]
+    #[test_case("logs/debug.log", vec!["!debug.log", "*.log"], true ; "Ensure the last matching pattern wins")]
+    #[test_case("logs/debug.log", vec!["*.log", "!debug.log"], false ; "Ensure a later negation overrides an earlier match")]
+    #[test_case("docs/build", vec!["build/"], false ; "Ensure directory patterns do not match files")]
+    fn test_ignore_rules(path: &str, patterns: Vec<&str>, expected_result: bool) {
+        let rules = to_ignore_rules(patterns);
+        assert_eq!(rules.is_ignored(Path::new(path), false), expected_result);
+    }
+
+    #[test_case("docs/notes.txt", true ; "Ensure nested rules apply inside their directory")]
+    #[test_case("notes.txt", false ; "Ensure nested rules do not apply outside their directory")]
+    #[test_case("docs/keep.md", false ; "Ensure nested negation overrides the parent rules")]
+    #[test_case("keep.md", true ; "Ensure nested negation does not apply outside its directory")]
+    #[test_case("docs/build.log", true ; "Ensure anchored nested rules match at their own level")]
+    #[test_case("docs/api/build.log", false ; "Ensure anchored nested rules are relative to their directory")]
+    fn test_nested_ignore_rules(path: &str, expected_result: bool) {
+        let mut rules = IgnoreRules::default();
+        rules.extend(Path::new(""), to_ignore_rules(vec!["*.md"]));
+        rules.extend(
+            Path::new("docs"),
+            to_ignore_rules(vec!["*.txt", "!keep.md", "/build.log"]),
+        );
+
+        assert_eq!(rules.is_ignored(Path::new(path), false), expected_result);
+    }
+
+    #[test_case(vec!["src/**/some_file2.rs"], vec!["src/core/some_file2.rs"] ; "Ensure middle double asterisk matches nested directories")]
+    #[test_case(vec!["**/__init__.py"], vec!["python/cli/__init__.py", ".venv/site-packages/__init__.py"] ; "Ensure leading double asterisk matches in all directories")]
+    #[test_case(vec!["src/**"], vec!["src/core/some_file.rs", "src/core/some_file2.rs"] ; "Ensure trailing double asterisk matches everything inside")]
+    #[test_case(vec!["core/*.rs"], vec![] ; "Ensure patterns with a middle slash are anchored to the root")]
+    #[test_case(vec!["*/some_file.rs"], vec!["scrap/some_file.rs"] ; "Ensure single asterisk does not cross directories")]
+    #[test_case(vec!["some_file.rs"], vec!["src/core/some_file.rs", "scrap/some_file.rs"] ; "Ensure patterns without a slash match at any level")]
+    #[test_case(vec!["/README.md", "/some_file.rs"], vec!["README.md"] ; "Ensure leading slash anchors to the root")]
+    fn test_filter_paths_gitignore_globs(patterns: Vec<&str>, ignored: Vec<&str>) {
+        let paths = to_pathbufs(get_mock_repo_vec());
+        let root = PathBuf::from("user/root/repo");
+        let gitignore = to_ignore_rules(patterns);
+
+        let expected_result: Vec<PathBuf> = paths
+            .iter()
+            .filter_map(|p| p.strip_prefix(&root).ok().map(Path::to_path_buf))
+            .filter(|p| !ignored.contains(&p.to_str().unwrap()))
+            .collect();
+
+        let actual_result = filter_paths(
+            paths,
+            &root,
+            &to_hashset(Vec::<&str>::new()),
+            &HashMap::new(),
+            &to_hashset(Vec::<&str>::new()),
+            &[gitignore],
             false,
         );
 
@@ -254,6 +972,260 @@ mod tests {
         assert_eq!(actual_result, expected_result);
     }
 
+    #[test]
+    fn test_file_tree_links() {
+        let paths = to_pathbufs(vec!["pkg/config.toml", "pkg/main.py"]);
+        let links = HashMap::from([
+            (
+                PathBuf::from("pkg/config.toml"),
+                PathBuf::from("../shared/config.toml"),
+            ),
+            (PathBuf::from("not/mapped"), PathBuf::from("../shared")),
+        ]);
+
+        let expected_result =
+            "# Repo map\n```\n└── pkg\n    ├── config.toml -> ../shared/config.toml\n    └── main.py\n::\n```";
+
+        let actual_result = FileTree::new().create_map(paths).add_links(&links).render();
+
+        assert_eq!(actual_result, expected_result);
+    }
+
+    #[test_case(
+        RenderLimits { max_depth: Some(1), max_children: None },
+        "├── pkg\n├── src\n└── setup.py" ;
+        "Ensure leaves out entries deeper than the max depth"
+    )]
+    #[test_case(
+        RenderLimits { max_depth: Some(0), max_children: None },
+        "└── … 2 more directories, 1 more file (1 .py)" ;
+        "Ensure only summarises the root at a max depth of 0"
+    )]
+    #[test_case(
+        RenderLimits { max_depth: Some(2), max_children: None },
+        "├── pkg\n│   ├── data\n│   ├── Makefile\n│   ├── a.py\n│   ├── b.py\n│   ├── c.json\n│   └── d.py\n├── src\n│   └── lib.rs\n└── setup.py" ;
+        "Ensure expands directories above the max depth"
+    )]
+    #[test_case(
+        RenderLimits { max_depth: None, max_children: Some(2) },
+        "├── pkg\n│   ├── data\n│   │   └── raw.csv\n│   ├── Makefile\n│   └── … 4 more files (3 .py, 1 .json)\n├── src\n│   └── lib.rs\n└── … 1 more file (1 .py)" ;
+        "Ensure collapses entries past the max children"
+    )]
+    #[test_case(
+        RenderLimits { max_depth: None, max_children: Some(0) },
+        "└── … 2 more directories, 1 more file (1 .py)" ;
+        "Ensure counts collapsed directories"
+    )]
+    fn test_file_tree_limits(limits: RenderLimits, expected_result: &str) {
+        let paths = to_pathbufs(vec![
+            "pkg/a.py",
+            "pkg/b.py",
+            "pkg/c.json",
+            "pkg/d.py",
+            "pkg/Makefile",
+            "pkg/data/raw.csv",
+            "src/lib.rs",
+            "setup.py",
+        ]);
+
+        let actual_result = FileTree::new().create_map(paths).render_tree(limits);
+
+        assert_eq!(actual_result, expected_result);
+    }
+
+    #[test]
+    fn test_file_tree_annotations() {
+        let paths = to_pathbufs(vec!["src/core/parsing.rs", "src/lib.rs", "setup.py"]);
+        let annotations = HashMap::from([
+            (
+                PathBuf::from("src/core/parsing.rs"),
+                "readme and gitignore parsing".to_string(),
+            ),
+            (PathBuf::from("setup.py"), "packaging".to_string()),
+            (PathBuf::from("not/mapped.py"), "skipped".to_string()),
+        ]);
+
+        let expected_result = [
+            "├── src",
+            "│   ├── core",
+            "│   │   └── parsing.rs  # readme and gitignore parsing",
+            "│   └── lib.rs",
+            "└── setup.py            # packaging",
+        ]
+        .join("\n");
+
+        let actual_result = FileTree::new()
+            .create_map(paths)
+            .add_annotations(&annotations)
+            .render_tree(RenderLimits::default());
+
+        assert_eq!(actual_result, expected_result);
+    }
+
+    #[test]
+    fn test_file_tree_stats() {
+        let paths = to_pathbufs(vec!["src/core/parsing.rs", "src/lib.rs", "setup.py"]);
+        let _stats = |size, lines, secs: Option<u64>| FileStats {
+            size: Some(size),
+            lines: Some(lines),
+            modified: secs.map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+        };
+        let stats = HashMap::from([
+            (
+                PathBuf::from("src/core/parsing.rs"),
+                _stats(2048, 50, Some(1_705_276_800)),
+            ),
+            (PathBuf::from("src/lib.rs"), _stats(100, 1, None)),
+            (
+                PathBuf::from("setup.py"),
+                _stats(500, 20, Some(951_782_400)),
+            ),
+        ]);
+        let annotations = HashMap::from([(PathBuf::from("setup.py"), "packaging".to_string())]);
+
+        let expected_result = [
+            "├── src                 2.1 KiB  51 lines  2024-01-15",
+            "│   ├── core            2.0 KiB  50 lines  2024-01-15",
+            "│   │   └── parsing.rs  2.0 KiB  50 lines  2024-01-15",
+            "│   └── lib.rs            100 B    1 line",
+            "└── setup.py              500 B  20 lines  2000-02-29  # packaging",
+        ]
+        .join("\n");
+
+        let actual_result = FileTree::new()
+            .create_map(paths)
+            .add_annotations(&annotations)
+            .add_stats(&stats)
+            .render_tree(RenderLimits::default());
+
+        assert_eq!(actual_result, expected_result);
+    }
+
+    #[test]
+    fn test_file_tree_dir_map_stats() {
+        let paths = to_pathbufs(vec!["a", "b", "c/d"]);
+        let stats = [("a/x.py", 10), ("b/y.py", 20), ("c/d/z.py", 5)]
+            .into_iter()
+            .map(|(path, lines)| {
+                let stats = FileStats {
+                    lines: Some(lines),
+                    ..FileStats::default()
+                };
+                (PathBuf::from(path), stats)
+            })
+            .collect();
+        let limits = RenderLimits {
+            max_depth: None,
+            max_children: Some(1),
+        };
+
+        let actual_result = FileTree::new()
+            .create_dir_map(paths)
+            .add_stats(&stats)
+            .render_tree(limits);
+
+        assert_eq!(
+            actual_result,
+            [
+                "├── c                      5 lines",
+                "│   └── d                  5 lines",
+                "└── … 2 more directories  30 lines",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_file_tree_list() {
+        let paths = to_pathbufs(vec!["src/__init__.py", "src/a b.py", "setup.py", "z.py"]);
+        let links = HashMap::from([(PathBuf::from("z.py"), PathBuf::from("../shared/z.py"))]);
+        let annotations = HashMap::from([(PathBuf::from("setup.py"), "packaging".to_string())]);
+        let limits = RenderLimits {
+            max_depth: None,
+            max_children: Some(2),
+        };
+
+        let expected_result = [
+            "- [src](../src)",
+            "  - [\\_\\_init\\_\\_.py](../src/__init__.py)",
+            "  - [a b.py](../src/a%20b.py)",
+            "- [setup.py](../setup.py) — packaging",
+            "- … 1 more file (1 .py)",
+        ]
+        .join("\n");
+
+        let actual_result = FileTree::new()
+            .create_map(paths)
+            .add_links(&links)
+            .add_annotations(&annotations)
+            .render_list(limits, "..");
+
+        assert_eq!(actual_result, expected_result);
+    }
+
+    #[test]
+    fn test_file_tree_html() {
+        let paths = to_pathbufs(vec!["src/a&b.py", "setup.py", "z.py"]);
+        let links = HashMap::from([(PathBuf::from("z.py"), PathBuf::from("<shared>"))]);
+        let annotations = HashMap::from([
+            (PathBuf::from("src/a&b.py"), "a & b".to_string()),
+            (PathBuf::from("setup.py"), "<packaging>".to_string()),
+        ]);
+
+        let expected_result = [
+            "├── <a href=\"docs/src\">src</a>",
+            "│   └── <a href=\"docs/src/a%26b.py\">a&amp;b.py</a>  # a &amp; b",
+            "├── <a href=\"docs/setup.py\">setup.py</a>    # &lt;packaging&gt;",
+            "└── <a href=\"docs/z.py\">z.py</a> -&gt; &lt;shared&gt;",
+        ]
+        .join("\n");
+
+        let actual_result = FileTree::new()
+            .create_map(paths)
+            .add_links(&links)
+            .add_annotations(&annotations)
+            .render_html(RenderLimits::default(), "docs");
+
+        assert_eq!(actual_result, expected_result);
+    }
+
+    #[test_case("", "src/lib.rs", "src/lib.rs" ; "Ensure links from the repo root")]
+    #[test_case("../..", "src/lib.rs", "../../src/lib.rs" ; "Ensure links up from the README")]
+    #[test_case("../src/", "my file (1).rs", "../src/my%20file%20%281%29.rs" ; "Ensure encodes reserved characters")]
+    #[test_case("", "ü.md", "%C3%BC.md" ; "Ensure encodes non ascii characters")]
+    fn test_href(base: &str, path: &str, expected_result: &str) {
+        assert_eq!(href(base, Path::new(path)), expected_result);
+    }
+
+    #[test_case(0, "0 B" ; "Ensure bytes have no decimals")]
+    #[test_case(1536, "1.5 KiB" ; "Ensure kibibytes have one decimal")]
+    #[test_case(5 * 1024 * 1024 * 1024, "5.0 GiB" ; "Ensure picks the largest unit")]
+    fn test_format_size(size: u64, expected_result: &str) {
+        assert_eq!(format_size(size), expected_result);
+    }
+
+    #[test_case(0, "1970-01-01" ; "Ensure the epoch is the first day")]
+    #[test_case(951_782_400, "2000-02-29" ; "Ensure counts leap days")]
+    #[test_case(1_700_000_000, "2023-11-14" ; "Ensure ignores the time of day")]
+    fn test_format_date(secs: u64, expected_result: &str) {
+        let time = UNIX_EPOCH + Duration::from_secs(secs);
+
+        assert_eq!(format_date(time).as_deref(), Some(expected_result));
+    }
+
+    #[test]
+    fn test_file_tree_dir_map_limits() {
+        let paths = to_pathbufs(vec!["a", "b", "c/d"]);
+        let limits = RenderLimits {
+            max_depth: None,
+            max_children: Some(1),
+        };
+
+        let actual_result = FileTree::new().create_dir_map(paths).render_tree(limits);
+
+        assert_eq!(actual_result, "├── c\n│   └── d\n└── … 2 more directories");
+    }
+
     #[test_case(
         vec![
             "path/repo/root/scripts/script_0.py",
@@ -1,65 +1,378 @@
 pub mod adapters;
+pub mod annotations;
+pub mod config;
 pub mod converters;
 pub mod domain;
 pub mod parsing;
+pub mod stats;
+pub mod walk;
 
 mod test_utils;
 use crate::core::adapters::FileSystem;
-use crate::core::domain::{filter_dirnames, filter_paths, FileTree, RetCode};
-use crate::core::parsing::{Args, GitIgnore, ReadMe};
+use crate::core::annotations::{read_annotations, read_descriptions};
+use crate::core::config::{parse_config, Options};
+use crate::core::converters::to_hashset;
+use crate::core::domain::{
+    filter_dirnames, filter_paths, FileTree, IgnoreLayer, IgnoreRules, RenderLimits, RenderMode,
+    RetCode,
+};
+use crate::core::parsing::{
+    parse_git_excludes, parse_ignore_file, parse_ignore_files, section, Args, BlockFormat,
+    DocFormat, GitIgnore, ReadMe, RepoMapBlock, REPOMAPIGNORE,
+};
+use crate::core::stats::read_stats;
+use crate::core::walk::{walk, Walk, WalkError, WalkErrorKind};
 use colored::Colorize;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
 
-#[allow(clippy::too_many_arguments)]
+/// Maps the repo. `options` are the ones given on the command line, any left unset are
+/// read from the config file in the repo root, if there is one.
 pub fn main(
     file_sys: &mut impl FileSystem,
     repo_root: String,
-    readme_path: String,
-    gitignore_path: String,
-    allowed_exts: Vec<String>,
-    ignore_dirs: Vec<String>,
-    ignore_hidden: bool,
-    dirs_only: bool,
+    options: Options,
+    check: bool,
+    stdout: bool,
 ) -> Result<RetCode, RetCode> {
-    let args = Args::new(
-        repo_root,
-        readme_path,
-        gitignore_path,
-        allowed_exts,
-        ignore_dirs,
-        ignore_hidden,
-        dirs_only,
-    );
-
-    let readme = ReadMe::parse(file_sys, &args.readme_path)?;
-    let gitignore = GitIgnore::parse(file_sys, &args.gitignore_path)?;
-    let paths = file_sys.list_files(&args.repo_root);
-
-    let paths: Vec<std::path::PathBuf> = filter_paths(
-        paths,
-        &args.repo_root,
-        &args.allowed_exts,
-        &args.ignore_dirs,
-        &gitignore.parse_lines(),
-        args.ignore_hidden,
-    );
+    let config = parse_config(file_sys, Path::new(&repo_root))?;
+    let args = Args::new(repo_root, options.or(config), check, stdout);
+    if !(1..=6).contains(&args.heading_level) {
+        eprintln!(
+            "{} `{}`, expected 1 to 6",
+            "Invalid heading level".red().bold(),
+            args.heading_level
+        );
+        return Err(RetCode::InvalidConfig);
+    }
+
+    let readme = match (&args.readme_path, args.stdout) {
+        (_, true) => None,
+        (Some(readme_path), false) => Some((readme_path, ReadMe::parse(file_sys, readme_path)?)),
+        (None, false) => {
+            eprintln!(
+                "{}",
+                "A README path is required unless writing to stdout"
+                    .red()
+                    .bold()
+            );
+            return Err(RetCode::InvalidFilename);
+        }
+    };
 
-    let paths = if args.dirs_only {
-        filter_dirnames(paths.clone())
+    // every block in the README is updated, as well as any declared only in the config
+    let mut blocks = match &readme {
+        Some((_, readme)) => readme.blocks()?,
+        None => Vec::new(),
+    };
+    for name in args.blocks.keys() {
+        if !blocks.iter().any(|block| block.name.as_ref() == Some(name)) {
+            blocks.push(RepoMapBlock::new(Some(name), args.block_format()));
+        }
+    }
+    if blocks.is_empty() {
+        blocks.push(RepoMapBlock::new(None, args.block_format()));
+    }
+    // legacy blocks are rewritten between markers, but other markups can't hold links
+    let clickable = args.render != RenderMode::Code;
+    let doc_format = blocks.iter().find_map(|block| match block.format {
+        BlockFormat::Markers(doc_format) if doc_format != DocFormat::Markdown => Some(doc_format),
+        _ => None,
+    });
+    if let (true, Some(doc_format)) = (clickable, doc_format) {
+        eprintln!(
+            "{}",
+            format!(
+                "Clickable maps (`--render list` or `html`) need a Markdown README, not {}",
+                doc_format.name()
+            )
+            .red()
+            .bold()
+        );
+        return Err(RetCode::InvalidConfig);
+    }
+    let heading = args.section_heading();
+
+    // the git index already reflects the gitignore rules, so they only apply to walked files
+    let mut layers = Vec::new();
+    if !args.git_tracked {
+        let gitignore = match &args.gitignore_path {
+            Some(path) => GitIgnore::parse(file_sys, path)?,
+            None => GitIgnore::discover(file_sys, &args.repo_root)?,
+        };
+        let gitignore_path = args
+            .gitignore_path
+            .clone()
+            .unwrap_or_else(|| args.repo_root.join(".gitignore"));
+
+        let excludes = if args.git_excludes {
+            let home = std::env::var_os("HOME").map(PathBuf::from);
+            let xdg_config_home = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from);
+            parse_git_excludes(
+                file_sys,
+                &args.repo_root,
+                home.as_deref(),
+                xdg_config_home.as_deref(),
+            )
+        } else {
+            IgnoreRules::default()
+        };
+        layers.push(gitignore.into_layer(&gitignore_path, &args.repo_root, excludes));
+    }
+
+    // other ignore sources are layered independently, so they can only exclude more paths
+    layers.push(IgnoreLayer::new(REPOMAPIGNORE));
+    let mut ignore_file_errors = Vec::new();
+    for filename in &args.ignore_files {
+        let mut layer = IgnoreLayer::root_only(filename);
+        let reader = file_sys.dir_reader();
+        if let Err(error) = parse_ignore_file(&reader, &mut layer, &args.repo_root, &args.repo_root)
+        {
+            ignore_file_errors.push(WalkError {
+                path: args.repo_root.join(filename),
+                kind: WalkErrorKind::ReadIgnoreFile,
+                error,
+            });
+        }
+        layers.push(layer);
+    }
+
+    let mut walked = if args.git_tracked {
+        let paths = match file_sys.list_git_files(&args.repo_root) {
+            Ok(paths) => paths,
+            Err(e) => {
+                eprintln!("{} {}", "Failed to run `git ls-files`: ".red().bold(), e);
+                return Err(RetCode::FailedListingGitFiles);
+            }
+        };
+        let reader = file_sys.dir_reader();
+        let errors = layers
+            .iter_mut()
+            .flat_map(|layer| parse_ignore_files(&reader, layer, &args.repo_root, &paths))
+            .collect();
+        Walk {
+            paths,
+            errors,
+            ..Walk::default()
+        }
     } else {
-        paths
+        walk(
+            file_sys,
+            &args.repo_root,
+            &args.ignore_dirs,
+            args.ignore_hidden,
+            args.symlinks,
+            &mut layers,
+        )
     };
+    walked.errors.splice(0..0, ignore_file_errors);
+    for error in &walked.errors {
+        eprintln!("{} {}", "Incomplete map:".yellow().bold(), error);
+    }
+    if args.strict && !walked.errors.is_empty() {
+        eprintln!(
+            "{}",
+            "Not updating the README, some paths could not be read"
+                .red()
+                .bold()
+        );
+        return Err(RetCode::IncompleteWalk);
+    }
 
-    let tree = FileTree::new().create_map(paths);
-    let modified_readme = readme.update_readme(tree.render());
+    let ignore_rules: Vec<IgnoreRules> = layers.into_iter().map(|layer| layer.rules).collect();
+    let descriptions = read_descriptions(file_sys, &args.repo_root)?;
+    // annotations are read once for every block, over the paths any extension would keep
+    let annotate = args.annotate
+        && blocks.iter().any(|block| {
+            !args
+                .block_options(block)
+                .dirs_only
+                .unwrap_or(args.dirs_only)
+        });
+    let annotations = if annotate {
+        let paths: Vec<PathBuf> = filter_paths(
+            walked.paths.clone(),
+            &args.repo_root,
+            &HashSet::new(),
+            &walked.links,
+            &args.ignore_dirs,
+            &ignore_rules,
+            args.ignore_hidden,
+        );
+        read_annotations(file_sys, &args.repo_root, &paths)
+    } else {
+        HashMap::new()
+    };
+
+    let repo_maps: Vec<(RepoMapBlock, String)> = blocks
+        .into_iter()
+        .map(|block| {
+            let repo_map = render_block(
+                file_sys,
+                &args,
+                &block,
+                &walked,
+                &ignore_rules,
+                &annotations,
+                &descriptions,
+            )?;
+            Ok((block, repo_map))
+        })
+        .collect::<Result<_, RetCode>>()?;
+
+    let Some((readme_path, readme)) = readme else {
+        let repo_maps: Vec<&str> = repo_maps.iter().map(|(_, map)| map.as_str()).collect();
+        if let Err(e) = file_sys.write_stdout(&section(heading.as_deref(), &repo_maps)) {
+            eprintln!("{} {}", "Failed to write to stdout: ".red().bold(), e);
+            return Err(RetCode::FailedToWriteReadme);
+        }
+        return Ok(RetCode::NoModification);
+    };
+    let modified_readme =
+        readme.update_readme(&repo_maps, heading.as_deref(), &args.insert_position);
 
     if modified_readme != readme {
-        if let Err(e) = modified_readme.write(file_sys, &args.readme_path) {
+        if args.check {
+            eprintln!("{}", "README repo map is out of date".red().bold());
+            for line in readme.diff_repo_map(&modified_readme).lines() {
+                match line.chars().next() {
+                    Some('+') => println!("{}", line.green()),
+                    Some('-') => println!("{}", line.red()),
+                    _ => println!("{line}"),
+                }
+            }
+            return Err(RetCode::OutdatedReadme);
+        }
+        if let Err(e) = modified_readme.write(file_sys, readme_path) {
             eprintln!("{} {}", "Failed to write README file: ".red().bold(), e);
             return Err(RetCode::FailedToWriteReadme);
         };
-        println!("{}", "Modified README.md".yellow().bold());
+        println!(
+            "{}",
+            format!("Modified {}", readme_path.display())
+                .yellow()
+                .bold()
+        );
         return Ok(RetCode::ModifiedReadme);
     }
     println!("{}", "Nothing to modify".green().bold());
     Ok(RetCode::NoModification)
 }
+
+/// Renders one block from the walked paths, its own settings taking precedence over those
+/// in the config, which take precedence over the global options. Manual `descriptions` take
+/// precedence over extracted `annotations`, both keyed relative to the repo root. Fails if the
+/// block's root isn't a walked directory.
+fn render_block(
+    file_sys: &mut impl FileSystem,
+    args: &Args,
+    block: &RepoMapBlock,
+    walked: &Walk,
+    ignore_rules: &[IgnoreRules],
+    annotations: &HashMap<PathBuf, String>,
+    descriptions: &HashMap<PathBuf, String>,
+) -> Result<String, RetCode> {
+    let options = args.block_options(block);
+    let root = match options.root() {
+        Ok(root) => root,
+        Err(e) => {
+            eprintln!("{} {}", "Invalid block root:".red().bold(), e);
+            return Err(RetCode::InvalidConfig);
+        }
+    };
+    let walked_root = args.repo_root.join(&root);
+    let has_root = root.as_os_str().is_empty()
+        || walked
+            .paths
+            .iter()
+            .any(|path| path.starts_with(&walked_root) && *path != walked_root);
+    if !has_root {
+        eprintln!(
+            "{} `{}`",
+            "No walked directory for the block root:".red().bold(),
+            root.display()
+        );
+        return Err(RetCode::InvalidConfig);
+    }
+    let allowed_exts = options
+        .allowed_exts
+        .map(to_hashset)
+        .unwrap_or_else(|| args.allowed_exts.clone());
+
+    let paths: Vec<PathBuf> = filter_paths(
+        walked.paths.clone(),
+        &args.repo_root,
+        &allowed_exts,
+        &walked.links,
+        &args.ignore_dirs,
+        ignore_rules,
+        args.ignore_hidden,
+    )
+    .into_iter()
+    .filter(|path| path.starts_with(&root))
+    .collect();
+    let dirs_only = options.dirs_only.unwrap_or(args.dirs_only);
+    let annotations: HashMap<PathBuf, String> = annotations
+        .iter()
+        .filter(|_| !dirs_only)
+        .chain(descriptions)
+        .filter_map(|(path, annotation)| {
+            Some((
+                path.strip_prefix(&root).ok()?.to_path_buf(),
+                annotation.clone(),
+            ))
+        })
+        .collect();
+    let paths: Vec<PathBuf> = paths
+        .into_iter()
+        .filter_map(|path| path.strip_prefix(&root).ok().map(Path::to_path_buf))
+        .collect();
+    let links: HashMap<PathBuf, PathBuf> = walked
+        .links
+        .iter()
+        .filter_map(|(path, target)| {
+            Some((path.strip_prefix(&root).ok()?.to_path_buf(), target.clone()))
+        })
+        .collect();
+
+    // links mapped as leaves aren't entered, so neither is what they point to measured
+    let stats = if args.metadata.is_empty() {
+        HashMap::new()
+    } else {
+        let files: Vec<PathBuf> = paths
+            .iter()
+            .filter(|path| !walked.links.contains_key(&root.join(path)))
+            .cloned()
+            .collect();
+        read_stats(
+            file_sys,
+            &args.repo_root.join(&root),
+            &files,
+            &args.metadata,
+        )
+    };
+
+    let tree = if dirs_only {
+        FileTree::new().create_dir_map(filter_dirnames(paths))
+    } else {
+        FileTree::new().create_map(paths)
+    };
+    let limits = RenderLimits {
+        max_depth: options.max_depth.or(args.max_depth),
+        max_children: options.max_children.or(args.max_children),
+    };
+
+    let base = format!("{}/{}", args.link_base(), root.to_string_lossy());
+
+    Ok(block.render(
+        &tree
+            .add_links(&links)
+            .add_annotations(&annotations)
+            .add_stats(&stats),
+        limits,
+        args.render,
+        &base,
+    ))
+}
@@ -1,39 +1,73 @@
-use crate::core::{adapters::FileSystem, converters::to_hashset, domain::RetCode};
+use crate::core::{
+    adapters::{DirReader, FileSystem},
+    config::{BlockOptions, Options},
+    converters::to_hashset,
+    domain::{
+        FileTree, IgnoreLayer, IgnorePattern, IgnoreRules, InsertPosition, MetadataColumn,
+        RenderLimits, RenderMode, RetCode, SymlinkMode,
+    },
+    walk::{WalkError, WalkErrorKind},
+};
 use colored::Colorize;
 use regex::Regex;
+use similar::TextDiff;
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
     io,
+    iter::Peekable,
+    ops::Range,
     path::{Path, PathBuf},
+    str::Chars,
 };
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct Args {
     pub repo_root: PathBuf,
-    pub readme_path: PathBuf,
-    pub gitignore_path: PathBuf,
+    pub readme_path: Option<PathBuf>,
+    pub gitignore_path: Option<PathBuf>,
     pub allowed_exts: HashSet<String>,
     pub ignore_dirs: HashSet<String>,
     pub ignore_hidden: bool,
     pub dirs_only: bool,
+    pub git_excludes: bool,
+    pub ignore_files: Vec<String>,
+    pub git_tracked: bool,
+    pub symlinks: SymlinkMode,
+    pub strict: bool,
+    pub heading: String,
+    pub heading_level: u8,
+    pub insert_position: InsertPosition,
+    pub max_depth: Option<usize>,
+    pub max_children: Option<usize>,
+    pub annotate: bool,
+    pub metadata: Vec<MetadataColumn>,
+    pub render: RenderMode,
+    pub check: bool,
+    pub stdout: bool,
+    pub blocks: BTreeMap<String, BlockOptions>,
 }
 
+pub const DEFAULT_ALLOWED_EXTS: [&str; 6] = ["py", "md", "toml", "lock", "yaml", "ipynb"];
+pub const DEFAULT_IGNORE_DIRS: [&str; 4] = [".git", ".venv", "build", "dist"];
+pub const DEFAULT_HEADING: &str = "Repo map";
+/// The settings a block accepts in its fence or marker, as listed in errors.
+const BLOCK_SETTINGS: &str = "`root`, `allowed-exts`, `dirs-only`, `max-depth` or `max-children`";
+
 impl Args {
-    pub fn new(
-        repo_root: String,
-        readme_path: String,
-        gitignore_path: String,
-        allowed_exts: Vec<String>,
-        ignore_dirs: Vec<String>,
-        ignore_hidden: bool,
-        dirs_only: bool,
-    ) -> Self {
+    /// Resolves the options, falling back to the defaults for any that are unset.
+    pub fn new(repo_root: String, options: Options, check: bool, stdout: bool) -> Self {
         let repo_root = PathBuf::from(repo_root);
-        let readme_path = PathBuf::from(readme_path);
-        let gitignore_path = PathBuf::from(gitignore_path);
+        let readme_path = options.readme_path.map(PathBuf::from);
+        let gitignore_path = options.gitignore_path.map(PathBuf::from);
 
-        let allowed_exts: HashSet<String> = to_hashset(allowed_exts);
-        let ignore_dirs: HashSet<String> = to_hashset(ignore_dirs);
+        let allowed_exts: HashSet<String> = options
+            .allowed_exts
+            .map(to_hashset)
+            .unwrap_or_else(|| to_hashset(DEFAULT_ALLOWED_EXTS));
+        let ignore_dirs: HashSet<String> = options
+            .ignore_dirs
+            .map(to_hashset)
+            .unwrap_or_else(|| to_hashset(DEFAULT_IGNORE_DIRS));
 
         Self {
             repo_root,
@@ -41,17 +75,116 @@ impl Args {
             gitignore_path,
             allowed_exts,
             ignore_dirs,
-            ignore_hidden,
-            dirs_only,
+            ignore_hidden: options.ignore_hidden.unwrap_or_default(),
+            dirs_only: options.dirs_only.unwrap_or_default(),
+            git_excludes: options.git_excludes.unwrap_or_default(),
+            ignore_files: options.ignore_files.unwrap_or_default(),
+            git_tracked: options.git_tracked.unwrap_or_default(),
+            symlinks: options.symlinks.unwrap_or_default(),
+            strict: options.strict.unwrap_or_default(),
+            heading: options
+                .heading
+                .unwrap_or_else(|| DEFAULT_HEADING.to_string()),
+            heading_level: options.heading_level.unwrap_or(1),
+            insert_position: options.insert_position.unwrap_or_default(),
+            max_depth: options.max_depth,
+            max_children: options.max_children,
+            annotate: options.annotate.unwrap_or_default(),
+            metadata: options.metadata.unwrap_or_default(),
+            render: options.render.unwrap_or_default(),
+            check,
+            stdout,
+            blocks: options.blocks.unwrap_or_default(),
+        }
+    }
+
+    /// The markup of the README, Markdown when printing to stdout without one.
+    pub fn doc_format(&self) -> DocFormat {
+        self.readme_path
+            .as_deref()
+            .map(DocFormat::from_path)
+            .unwrap_or_default()
+    }
+
+    /// The heading new blocks are put under, `None` for the legacy `# Repo map` heading that
+    /// is part of the block itself.
+    pub fn section_heading(&self) -> Option<String> {
+        let doc_format = self.doc_format();
+        if doc_format == DocFormat::Markdown
+            && self.heading == DEFAULT_HEADING
+            && self.heading_level == 1
+        {
+            return None;
+        }
+        Some(doc_format.heading(&self.heading, self.heading_level))
+    }
+
+    /// How new blocks are delimited, the legacy format needs the default heading to be found
+    /// and can only hold a fenced tree.
+    pub fn block_format(&self) -> BlockFormat {
+        match (self.section_heading(), self.render) {
+            (None, RenderMode::Code) => BlockFormat::Heading,
+            _ => BlockFormat::Markers(self.doc_format()),
+        }
+    }
+
+    /// The settings of `block`, its own taking precedence over those in the config. Unset ones
+    /// fall back to the global options.
+    pub fn block_options(&self, block: &RepoMapBlock) -> BlockOptions {
+        let config = block
+            .name
+            .as_ref()
+            .and_then(|name| self.blocks.get(name))
+            .cloned()
+            .unwrap_or_default();
+        block.options.clone().or(config)
+    }
+
+    /// The path from the README's directory back up to the repo root, where links in the map
+    /// start from. Maps written to stdout aren't in the README, so their links start from the
+    /// repo root.
+    pub fn link_base(&self) -> String {
+        if self.stdout {
+            return String::new();
         }
+        let depth = self
+            .readme_path
+            .as_deref()
+            .and_then(Path::parent)
+            .and_then(|dir| dir.strip_prefix(&self.repo_root).ok())
+            .map_or(0, |dir| dir.components().count());
+        vec![".."; depth].join("/")
     }
 }
 
 trait FileText: Sized {
-    const EXPECTED_FILENAME: &'static str;
-
     fn from_string(s: String) -> Self;
 
+    /// Reads the file without checking its basename, for sources that can have any name.
+    fn read(file_sys: &mut impl FileSystem, path: &Path) -> Result<Self, RetCode> {
+        match file_sys.read_to_string(path) {
+            Ok(contents) => Ok(Self::from_string(contents)),
+            Err(e) => Err(report_read_error(path, e)),
+        }
+    }
+
+    /// Like `read`, but a missing file is `Ok(None)` rather than an error.
+    fn read_if_exists(
+        file_sys: &mut impl FileSystem,
+        path: &Path,
+    ) -> Result<Option<Self>, RetCode> {
+        match file_sys.read_to_string(path) {
+            Ok(contents) => Ok(Some(Self::from_string(contents))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(report_read_error(path, e)),
+        }
+    }
+}
+
+/// A file that must have one name, so a path given for it is checked first.
+trait NamedFileText: FileText {
+    const EXPECTED_FILENAME: &'static str;
+
     fn parse(file_sys: &mut impl FileSystem, path: impl AsRef<Path>) -> Result<Self, RetCode> {
         let path = path.as_ref().to_path_buf();
         let basename = path
@@ -73,154 +206,1140 @@ trait FileText: Sized {
             return Err(RetCode::InvalidFilename);
         }
 
-        match file_sys.read_to_string(&path) {
-            Ok(contents) => Ok(Self::from_string(contents)),
-            Err(e) => {
-                eprintln!(
-                    "{} {}",
-                    format!("Failed to parse `{}`", Self::EXPECTED_FILENAME)
-                        .red()
-                        .bold(),
-                    e
-                );
-                Err(RetCode::FailedParsingFile)
-            }
-        }
+        Self::read(file_sys, &path)
     }
 }
 
+fn report_read_error(path: &Path, e: io::Error) -> RetCode {
+    eprintln!(
+        "{} {}",
+        format!("Failed to parse `{}`", path.display()).red().bold(),
+        e
+    );
+    RetCode::FailedParsingFile
+}
+
+pub const REPOMAPIGNORE: &str = ".repomapignore";
+
+/// Text in gitignore syntax, read from `.gitignore` or any other ignore source.
 #[derive(Debug, Eq, PartialEq)]
 pub struct GitIgnore(String);
 
 impl FileText for GitIgnore {
-    const EXPECTED_FILENAME: &'static str = ".gitignore";
-
     fn from_string(s: String) -> Self {
         GitIgnore(s)
     }
 }
 
+impl NamedFileText for GitIgnore {
+    const EXPECTED_FILENAME: &'static str = ".gitignore";
+}
+
 impl GitIgnore {
     pub fn parse(file_sys: &mut impl FileSystem, path: impl AsRef<Path>) -> Result<Self, RetCode> {
-        <Self as FileText>::parse(file_sys, path)
+        <Self as NamedFileText>::parse(file_sys, path)
     }
 
-    pub fn parse_lines(&self) -> Vec<Regex> {
-        self.0
-            .lines()
-            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
-            .filter_map(|pattern| {
-                let mut regex_str = String::new();
-
-                regex_str.push_str("(^|/)");
-                let pattern = pattern.trim_start_matches("/");
-
-                for c in pattern.chars() {
-                    match c {
-                        '*' => regex_str.push_str("[^/]*"),
-                        '?' => regex_str.push('.'),
-                        '.' => regex_str.push_str(r"\."),
-                        _ => regex_str.push(c),
+    /// Reads `<repo_root>/.gitignore`, treating a missing file as one without any patterns.
+    pub fn discover(file_sys: &mut impl FileSystem, repo_root: &Path) -> Result<Self, RetCode> {
+        let path = repo_root.join(Self::EXPECTED_FILENAME);
+        Ok(Self::read_if_exists(file_sys, &path)?.unwrap_or(GitIgnore(String::new())))
+    }
+
+    pub fn parse_lines(&self) -> IgnoreRules {
+        let mut patterns = Vec::new();
+        for (i, line) in self.0.lines().enumerate() {
+            match parse_pattern(line) {
+                Ok(Some(pattern)) => patterns.push(pattern),
+                Ok(None) => {}
+                Err(e) => eprintln!(
+                    "{} line {}: `{}` ({})",
+                    "Skipping invalid ignore pattern on".yellow().bold(),
+                    i + 1,
+                    line,
+                    e
+                ),
+            }
+        }
+        IgnoreRules::new(patterns)
+    }
+
+    /// Starts the `.gitignore` layer with these rules, read from `path`, on top of `rules`.
+    pub fn into_layer(self, path: &Path, repo_root: &Path, mut rules: IgnoreRules) -> IgnoreLayer {
+        let base = path
+            .parent()
+            .and_then(|dir| dir.strip_prefix(repo_root).ok())
+            .unwrap_or(Path::new(""));
+        rules.extend(base, self.parse_lines());
+
+        IgnoreLayer {
+            filename: Self::EXPECTED_FILENAME.to_string(),
+            rules,
+            loaded: Some(path.to_path_buf()),
+            nested: true,
+        }
+    }
+}
+
+/// Reads the ignore file at `path` in gitignore syntax, `None` if there is none.
+pub fn read_ignore_file(reader: &impl DirReader, path: &Path) -> io::Result<Option<IgnoreRules>> {
+    match reader.read_to_string(path) {
+        Ok(contents) => Ok(Some(GitIgnore(contents).parse_lines())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the layer's ignore file in `dir`, if there is one, scoping its rules to `dir`.
+pub fn parse_ignore_file(
+    reader: &impl DirReader,
+    layer: &mut IgnoreLayer,
+    repo_root: &Path,
+    dir: &Path,
+) -> io::Result<()> {
+    let path = dir.join(&layer.filename);
+    if layer.loaded.as_deref() == Some(path.as_path()) {
+        return Ok(());
+    }
+    let Ok(base) = dir.strip_prefix(repo_root) else {
+        return Ok(());
+    };
+    if let Some(rules) = read_ignore_file(reader, &path)? {
+        layer.rules.extend(base, rules);
+    }
+    Ok(())
+}
+
+/// Applies every ignore file in `paths` named after the layer, for path sources that aren't
+/// walked. Shallower files are applied first so deeper ones take precedence. Layers that
+/// aren't nested are left as they are. Returns the files that couldn't be read.
+pub fn parse_ignore_files(
+    reader: &impl DirReader,
+    layer: &mut IgnoreLayer,
+    repo_root: &Path,
+    paths: &[PathBuf],
+) -> Vec<WalkError> {
+    let mut errors = Vec::new();
+    if !layer.nested {
+        return errors;
+    }
+    let mut dirs: Vec<&Path> = paths
+        .iter()
+        .filter(|p| p.file_name().and_then(|s| s.to_str()) == Some(layer.filename.as_str()))
+        .filter_map(|p| p.parent())
+        .collect();
+    dirs.sort_by_key(|dir| (dir.components().count(), dir.to_path_buf()));
+
+    for dir in dirs {
+        // git never reads ignore files inside an excluded directory
+        if let Ok(base) = dir.strip_prefix(repo_root) {
+            if !base.as_os_str().is_empty() && layer.rules.is_ignored(base, true) {
+                continue;
+            }
+        }
+        if let Err(error) = parse_ignore_file(reader, layer, repo_root, dir) {
+            errors.push(WalkError {
+                path: dir.join(&layer.filename),
+                kind: WalkErrorKind::ReadIgnoreFile,
+                error,
+            });
+        }
+    }
+    errors
+}
+
+/// Reads the exclude files git consults besides `.gitignore`, lowest precedence first:
+/// `core.excludesFile` (defaulting to `$XDG_CONFIG_HOME/git/ignore`) then `.git/info/exclude`.
+/// A relative `core.excludesFile` is relative to the repo root, as for git. Missing files are
+/// skipped.
+pub fn parse_git_excludes(
+    file_sys: &mut impl FileSystem,
+    repo_root: &Path,
+    home: Option<&Path>,
+    xdg_config_home: Option<&Path>,
+) -> IgnoreRules {
+    let xdg_config_home = xdg_config_home
+        .map(Path::to_path_buf)
+        .or_else(|| home.map(|home| home.join(".config")));
+
+    // later config files override earlier ones, matching git's global then local order
+    let config_paths = [
+        xdg_config_home.as_ref().map(|xdg| xdg.join("git/config")),
+        home.map(|home| home.join(".gitconfig")),
+        Some(repo_root.join(".git/config")),
+    ];
+    let excludes_file = config_paths
+        .into_iter()
+        .flatten()
+        .filter_map(|path| file_sys.read_to_string(&path).ok())
+        .filter_map(|config| parse_excludes_file(&config, repo_root, home))
+        .last()
+        .or_else(|| xdg_config_home.map(|xdg| xdg.join("git/ignore")));
+
+    let mut rules = IgnoreRules::default();
+    for path in excludes_file
+        .into_iter()
+        .chain([repo_root.join(".git/info/exclude")])
+    {
+        if let Ok(contents) = file_sys.read_to_string(&path) {
+            rules.extend(Path::new(""), GitIgnore(contents).parse_lines());
+        }
+    }
+    rules
+}
+
+/// Finds `core.excludesFile` in a git config file, expanding a leading `~/` and resolving
+/// relative paths against `repo_root`.
+fn parse_excludes_file(config: &str, repo_root: &Path, home: Option<&Path>) -> Option<PathBuf> {
+    let mut in_core = false;
+    let mut excludes_file = None;
+
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_core = line
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .trim()
+                .eq_ignore_ascii_case("core");
+            continue;
+        }
+        if !in_core || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if key.trim().eq_ignore_ascii_case("excludesfile") {
+            let value = value.split([';', '#']).next().unwrap_or_default().trim();
+            excludes_file = Some(value.trim_matches('"').to_string());
+        }
+    }
+
+    excludes_file.map(|value| match (value.strip_prefix("~/"), home) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => repo_root.join(value),
+    })
+}
+
+/// Compiles a single gitignore line, `Ok(None)` for blank lines and comments.
+pub fn parse_pattern(line: &str) -> Result<Option<IgnorePattern>, String> {
+    let line = trim_trailing_spaces(line);
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let (negated, pattern) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (dir_only, pattern) = match pattern.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, pattern),
+    };
+
+    // a slash at the start or in the middle anchors the pattern to the `.gitignore` directory
+    let anchored = pattern.contains('/');
+    let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+
+    let mut regex_str = String::from(if anchored { "^" } else { "(^|/)" });
+
+    let segments: Vec<&str> = pattern.split('/').collect();
+    for (i, segment) in segments.iter().enumerate() {
+        let is_last = i == segments.len() - 1;
+
+        if *segment == "**" {
+            // trailing `/**` matches everything inside, leading `**/` and middle `/**/` match zero or more directories
+            regex_str.push_str(if is_last { ".*" } else { "(.*/)?" });
+            continue;
+        }
+
+        translate_segment(segment, &mut regex_str)?;
+        if !is_last {
+            regex_str.push('/');
+        }
+    }
+    regex_str.push('$');
+
+    let regex = Regex::new(&regex_str).map_err(|e| e.to_string())?;
+    Ok(Some(IgnorePattern {
+        regex,
+        negated,
+        dir_only,
+        base: PathBuf::new(),
+    }))
+}
+
+fn trim_trailing_spaces(line: &str) -> &str {
+    let mut line = line;
+    while line.ends_with(' ') && !line.ends_with("\\ ") {
+        line = &line[..line.len() - 1];
+    }
+    line
+}
+
+fn translate_segment(segment: &str, regex_str: &mut String) -> Result<(), String> {
+    let mut chars = segment.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex_str.push_str("[^/]*"),
+            '?' => regex_str.push_str("[^/]"),
+            '[' => translate_class(&mut chars, regex_str)?,
+            '\\' => match chars.next() {
+                Some(escaped) => regex_str.push_str(&regex::escape(&escaped.to_string())),
+                None => return Err("trailing backslash".to_string()),
+            },
+            _ => regex_str.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    Ok(())
+}
+
+fn translate_class(chars: &mut Peekable<Chars>, regex_str: &mut String) -> Result<(), String> {
+    #[inline(always)]
+    fn _push_literal(c: char, class: &mut String) {
+        if c.is_ascii_punctuation() {
+            class.push('\\');
+        }
+        class.push(c);
+    }
+
+    let mut class = String::from("[");
+    if matches!(chars.peek(), Some('!') | Some('^')) {
+        chars.next();
+        class.push_str("^/");
+    }
+
+    // a `]` straight after the opening bracket is a literal, not the end of the class
+    let mut is_first = true;
+    loop {
+        match chars.next() {
+            None => return Err("unclosed character class".to_string()),
+            Some(']') if !is_first => break,
+            Some('\\') => match chars.next() {
+                Some(escaped) => _push_literal(escaped, &mut class),
+                None => return Err("unclosed character class".to_string()),
+            },
+            Some('[') if chars.peek() == Some(&':') => {
+                chars.next();
+                class.push_str("[:");
+                loop {
+                    match chars.next() {
+                        None => return Err("unclosed character class".to_string()),
+                        Some(':') if chars.peek() == Some(&']') => {
+                            chars.next();
+                            class.push_str(":]");
+                            break;
+                        }
+                        Some(c) => class.push(c),
                     }
                 }
+            }
+            Some('-') => class.push('-'),
+            Some(c) => _push_literal(c, &mut class),
+        }
+        is_first = false;
+    }
+    class.push(']');
+    regex_str.push_str(&class);
+    Ok(())
+}
 
-                if pattern.ends_with('/') {
-                    regex_str.push_str("(.*)?$");
-                } else {
-                    regex_str.push('$');
-                }
+/// The markup of the file holding the repo map, picked from its extension.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DocFormat {
+    #[default]
+    Markdown,
+    ReStructuredText,
+    AsciiDoc,
+}
+
+/// reStructuredText section underlines, from the highest level down.
+const RST_ADORNMENTS: [char; 6] = ['=', '-', '~', '^', '"', '\''];
+
+impl DocFormat {
+    /// Files with any other extension are taken to be Markdown.
+    pub fn from_path(path: &Path) -> Self {
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        match ext.as_deref() {
+            Some("rst" | "rest") => Self::ReStructuredText,
+            Some("adoc" | "asciidoc" | "asc") => Self::AsciiDoc,
+            _ => Self::Markdown,
+        }
+    }
 
-                Regex::new(&regex_str).ok()
+    /// The name of the markup, as shown in errors.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Markdown => "Markdown",
+            Self::ReStructuredText => "reStructuredText",
+            Self::AsciiDoc => "AsciiDoc",
+        }
+    }
+
+    /// Matches a block between the start and end markers, capturing the start one as `marker`.
+    fn markers_pattern(self) -> &'static str {
+        match self {
+            Self::Markdown => {
+                r"^<!--[ \t]*(?P<marker>repo-map:start[^\n]*?)[ \t]*-->\n.*?^<!--[ \t]*repo-map:end[ \t]*-->"
+            }
+            Self::ReStructuredText => {
+                r"^\.\.[ \t]+(?P<marker>repo-map:start[^\n]*?)[ \t]*\n.*?^\.\.[ \t]+repo-map:end[ \t]*$"
+            }
+            Self::AsciiDoc => {
+                r"^//[ \t]*(?P<marker>repo-map:start[^\n]*?)[ \t]*\n.*?^//[ \t]*repo-map:end[ \t]*$"
+            }
+        }
+    }
+
+    /// Fences the tree as a literal block between comments holding the `marker` text.
+    fn render_markers(self, marker: &str, tree: &str) -> String {
+        match self {
+            Self::Markdown => {
+                format!("<!-- {marker} -->\n```\n{tree}\n```\n<!-- repo-map:end -->")
+            }
+            Self::ReStructuredText => {
+                let tree: Vec<String> = tree.lines().map(|line| format!("   {line}")).collect();
+                format!(
+                    ".. {marker}\n\n.. code-block:: text\n\n{}\n\n.. repo-map:end",
+                    tree.join("\n")
+                )
+            }
+            Self::AsciiDoc => format!("// {marker}\n----\n{tree}\n----\n// repo-map:end"),
+        }
+    }
+
+    /// A heading of `level`, 1 being the highest.
+    pub fn heading(self, text: &str, level: u8) -> String {
+        let level = level.clamp(1, 6) as usize;
+        match self {
+            Self::Markdown => format!("{} {text}", "#".repeat(level)),
+            Self::ReStructuredText => {
+                let underline = RST_ADORNMENTS[level - 1]
+                    .to_string()
+                    .repeat(text.chars().count());
+                format!("{text}\n{underline}\n")
+            }
+            Self::AsciiDoc => format!("{} {text}", "=".repeat(level)),
+        }
+    }
+
+    /// The headings in `text` as their byte offset, level and title, skipping literal blocks.
+    /// reStructuredText levels follow the order the underlines first appear in.
+    fn headings(self, text: &str) -> Vec<(usize, usize, &str)> {
+        let mut offset = 0;
+        let lines: Vec<(usize, &str)> = text
+            .split_inclusive('\n')
+            .map(|line| {
+                let start = offset;
+                offset += line.len();
+                (start, line.trim_end())
             })
-            .collect()
+            .collect();
+
+        let prefix = match self {
+            Self::Markdown => '#',
+            Self::AsciiDoc => '=',
+            Self::ReStructuredText => return rst_headings(&lines),
+        };
+        let code = self.code_ranges(text);
+        let mut headings = Vec::new();
+        for (start, line) in lines {
+            if in_code(&code, start) {
+                continue;
+            }
+            let level = line.chars().take_while(|c| *c == prefix).count();
+            let title = &line[level..];
+            if (1..=6).contains(&level) && title.starts_with([' ', '\t']) {
+                headings.push((start, level, title.trim()));
+            }
+        }
+        headings
+    }
+
+    /// The fence character and length if `line` opens or closes a block of code, along with
+    /// the text after the fence.
+    fn fence(self, line: &str) -> Option<(char, usize, &str)> {
+        let c = line.chars().next()?;
+        let len = line.chars().take_while(|fence| *fence == c).count();
+        let rest = line[len * c.len_utf8()..].trim();
+        match (self, c) {
+            (Self::Markdown, '`' | '~') | (Self::AsciiDoc, '`') if len >= 3 => Some((c, len, rest)),
+            (Self::AsciiDoc, '-' | '.') if len >= 4 && rest.is_empty() => Some((c, len, rest)),
+            _ => None,
+        }
+    }
+
+    /// The byte ranges of fenced code in Markdown and of listing and literal blocks in
+    /// AsciiDoc, so examples of repo map blocks aren't taken for real ones. Literal blocks in
+    /// reStructuredText are indented, so they can't hold a marker anyway.
+    fn code_ranges(self, text: &str) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        let mut open: Option<(usize, char, usize)> = None;
+        let mut offset = 0;
+
+        for line in text.split_inclusive('\n') {
+            let start = offset;
+            offset += line.len();
+            let Some((c, len, rest)) = self.fence(line.trim()) else {
+                continue;
+            };
+            match open {
+                None => open = Some((start, c, len)),
+                Some((open_start, open_c, open_len))
+                    if c == open_c && len >= open_len && rest.is_empty() =>
+                {
+                    ranges.push(open_start..offset);
+                    open = None;
+                }
+                _ => {}
+            }
+        }
+        // an unclosed fence runs to the end of the document
+        if let Some((open_start, _, _)) = open {
+            ranges.push(open_start..text.len());
+        }
+        ranges
+    }
+
+    /// The byte offset where the section under the heading `heading` ends, i.e. the start of
+    /// the next heading of the same or a higher level.
+    fn end_of_section(self, text: &str, heading: &str) -> Option<usize> {
+        let heading = heading.trim_start_matches(['#', '=']).trim();
+        let headings = self.headings(text);
+        let i = headings
+            .iter()
+            .position(|(_, _, title)| *title == heading)?;
+        let level = headings[i].1;
+        Some(
+            headings[i + 1..]
+                .iter()
+                .find(|(_, next_level, _)| *next_level <= level)
+                .map_or(text.len(), |(start, _, _)| *start),
+        )
+    }
+}
+
+fn in_code(code: &[Range<usize>], offset: usize) -> bool {
+    code.iter().any(|range| range.contains(&offset))
+}
+
+fn rst_headings<'a>(lines: &[(usize, &'a str)]) -> Vec<(usize, usize, &'a str)> {
+    let mut adornments: Vec<char> = Vec::new();
+    let mut headings = Vec::new();
+
+    for (i, pair) in lines.windows(2).enumerate() {
+        let [(start, title), (_, underline)] = pair else {
+            continue;
+        };
+        let Some(c) = underline.chars().next() else {
+            continue;
+        };
+        let is_underline = c.is_ascii_punctuation()
+            && underline.chars().all(|u| u == c)
+            && underline.chars().count() >= title.chars().count();
+        if !is_underline
+            || title.trim().is_empty()
+            || title.starts_with([' ', '\t'])
+            || title.chars().all(|t| t == c)
+        {
+            continue;
+        }
+
+        let level = match adornments.iter().position(|a| *a == c) {
+            Some(i) => i + 1,
+            None => {
+                adornments.push(c);
+                adornments.len()
+            }
+        };
+        // a title with an overline starts a line earlier
+        let start = match i.checked_sub(1).map(|j| lines[j]) {
+            Some((over_start, over)) if over == *underline => over_start,
+            _ => *start,
+        };
+        headings.push((start, level, title.trim()));
     }
+    headings
 }
 
 #[derive(Debug, Eq, PartialEq)]
-pub struct ReadMe(String);
+pub struct ReadMe(String, DocFormat);
 
 impl FileText for ReadMe {
-    const EXPECTED_FILENAME: &'static str = "README.md";
-
     fn from_string(s: String) -> Self {
-        ReadMe(s)
+        ReadMe(s, DocFormat::Markdown)
     }
 }
 
 impl ReadMe {
+    /// Reads the file the map goes in, which can have any name. Its markup is picked from
+    /// the extension.
     pub fn parse(file_sys: &mut impl FileSystem, path: impl AsRef<Path>) -> Result<Self, RetCode> {
-        <Self as FileText>::parse(file_sys, path)
+        let path = path.as_ref();
+        let ReadMe(text, _) = Self::read(file_sys, path)?;
+        Ok(ReadMe(text, DocFormat::from_path(path)))
     }
 
     pub fn write(&self, file_sys: &mut impl FileSystem, path: &Path) -> Result<(), io::Error> {
         file_sys.write(path, &self.0)
     }
 
-    pub fn update_readme(&self, repo_map: String) -> ReadMe {
-        let pattern = Regex::new(r"(?s)(?m)^# Repo map\n```.*?^::\n```").expect("valid regex");
+    /// Matches blocks between the format's start and end markers, and in Markdown also the
+    /// legacy `# Repo map` heading with its `::` sentinel.
+    fn repo_map_pattern(&self) -> Regex {
+        let markers = self.1.markers_pattern();
+        let pattern = match self.1 {
+            DocFormat::Markdown => {
+                format!(r"(?s)(?m)^# Repo map\n```(?P<info>[^\n]*)\n.*?^::\n```|{markers}")
+            }
+            _ => format!("(?s)(?m){markers}"),
+        };
+        Regex::new(&pattern).expect("valid regex")
+    }
+
+    /// The repo map blocks in the README, in order.
+    pub fn blocks(&self) -> Result<Vec<RepoMapBlock>, RetCode> {
+        let code = self.1.code_ranges(&self.0);
+        self.repo_map_pattern()
+            .captures_iter(&self.0)
+            .filter(|caps| !caps.get(0).is_some_and(|m| in_code(&code, m.start())))
+            .map(|caps| {
+                RepoMapBlock::from_captures(&caps, self.1).map_err(|e| {
+                    eprintln!(
+                        "{} `{}` ({})",
+                        "Invalid repo map block".red().bold(),
+                        caps.name("marker")
+                            .or(caps.name("info"))
+                            .map_or("", |m| m.as_str()),
+                        e
+                    );
+                    RetCode::InvalidConfig
+                })
+            })
+            .collect()
+    }
+
+    /// The text of every repo map block in the README.
+    pub fn repo_maps(&self) -> Vec<&str> {
+        let code = self.1.code_ranges(&self.0);
+        self.repo_map_pattern()
+            .find_iter(&self.0)
+            .filter(|block| !in_code(&code, block.start()))
+            .map(|block| block.as_str())
+            .collect()
+    }
+
+    /// A unified diff from this README's repo map blocks to the ones in `updated`.
+    pub fn diff_repo_map(&self, updated: &ReadMe) -> String {
+        let current = self.repo_maps().join("\n\n");
+        let updated = updated.repo_maps().join("\n\n");
+        TextDiff::from_lines(&current, &updated)
+            .unified_diff()
+            .header("current", "updated")
+            .to_string()
+    }
+
+    /// Replaces each block with the repo map rendered for it. Blocks not in the README yet are
+    /// inserted at `position` together, under `heading` if given.
+    pub fn update_readme(
+        &self,
+        repo_maps: &[(RepoMapBlock, String)],
+        heading: Option<&str>,
+        position: &InsertPosition,
+    ) -> ReadMe {
+        let pattern = self.repo_map_pattern();
+        let code = self.1.code_ranges(&self.0);
+        let mut existing = HashSet::new();
+
+        let updated = pattern
+            .replace_all(&self.0, |caps: &regex::Captures| {
+                if caps.get(0).is_some_and(|m| in_code(&code, m.start())) {
+                    return caps[0].to_string();
+                }
+                let Ok(parsed) = RepoMapBlock::from_captures(caps, self.1) else {
+                    return caps[0].to_string();
+                };
+                existing.insert(parsed.name.clone());
+                repo_maps
+                    .iter()
+                    .find(|(block, _)| *block == parsed)
+                    .map(|(_, repo_map)| repo_map.clone())
+                    .unwrap_or_else(|| caps[0].to_string())
+            })
+            .into_owned();
+
+        let new_maps: Vec<&str> = repo_maps
+            .iter()
+            .filter(|(block, _)| existing.insert(block.name.clone()))
+            .map(|(_, repo_map)| repo_map.as_str())
+            .collect();
+        if new_maps.is_empty() {
+            return ReadMe(updated, self.1);
+        }
+        let section = section(heading, &new_maps);
+
+        let end_of_section = match position {
+            InsertPosition::End => None,
+            InsertPosition::Start => {
+                return ReadMe(format!("{section}\n\n{updated}"), self.1);
+            }
+            InsertPosition::After(after) => {
+                let end = self.1.end_of_section(&updated, after);
+                if end.is_none() {
+                    eprintln!(
+                        "{} `{}`, appending the repo map instead",
+                        "No heading".yellow().bold(),
+                        after
+                    );
+                }
+                end
+            }
+        };
+        match end_of_section {
+            Some(end) if end < updated.len() => {
+                let (before, after) = updated.split_at(end);
+                let separator = if before.ends_with("\n\n") { "" } else { "\n" };
+                ReadMe(format!("{before}{separator}{section}\n\n{after}"), self.1)
+            }
+            _ => ReadMe(
+                format!("{}\n\n{section}", updated.trim_end_matches('\n')),
+                self.1,
+            ),
+        }
+    }
+}
+
+/// Joins repo maps into one section, under `heading` if given.
+pub fn section(heading: Option<&str>, repo_maps: &[&str]) -> String {
+    let repo_maps = repo_maps.join("\n\n");
+    match heading {
+        Some(heading) => format!("{heading}\n{repo_maps}"),
+        None => repo_maps,
+    }
+}
+
+/// How a repo map block is delimited in the README.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BlockFormat {
+    /// A `# Repo map` heading followed by a fence ending in a `::` line.
+    #[default]
+    Heading,
+    /// A literal block between `repo-map:start` and `repo-map:end` comments, e.g.
+    /// `<!-- repo-map:start -->` in Markdown.
+    Markers(DocFormat),
+}
+
+/// A repo map block, marked in the README by its fence's info string, e.g.
+/// `repo-map:src root=src dirs-only=true`, or its start marker, e.g.
+/// `<!-- repo-map:start:src root=src -->` in Markdown. Blocks without a name use the global options.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RepoMapBlock {
+    pub name: Option<String>,
+    /// The settings in the marker, they take precedence over the config.
+    pub options: BlockOptions,
+    /// The fence's info string or the start marker's text, kept as written.
+    pub info: String,
+    pub format: BlockFormat,
+}
 
-        let updated = if pattern.is_match(&self.0) {
-            pattern.replace(&self.0, repo_map).into_owned()
-        } else {
-            format!("{}\n\n{}", self.0, repo_map)
+impl RepoMapBlock {
+    /// A block not in the README yet, named if declared in the config.
+    pub fn new(name: Option<&str>, format: BlockFormat) -> Self {
+        let info = match (name, format) {
+            (None, BlockFormat::Heading) => String::new(),
+            (Some(name), BlockFormat::Heading) => format!("repo-map:{name}"),
+            (None, BlockFormat::Markers(_)) => "repo-map:start".to_string(),
+            (Some(name), BlockFormat::Markers(_)) => format!("repo-map:start:{name}"),
         };
-        ReadMe(updated)
+        Self {
+            name: name.map(str::to_string),
+            options: BlockOptions::default(),
+            info,
+            format,
+        }
+    }
+
+    fn from_captures(caps: &regex::Captures, doc_format: DocFormat) -> Result<Self, String> {
+        match caps.name("marker") {
+            Some(marker) => Self::parse_marker(marker.as_str(), doc_format),
+            None => Self::parse_info(&caps["info"]),
+        }
+    }
+
+    /// Parses the text of a start marker, `repo-map:start` optionally followed by the same
+    /// name and settings as an info string, e.g. `repo-map:start:src root=src`.
+    pub fn parse_marker(marker: &str, doc_format: DocFormat) -> Result<Self, String> {
+        let rest = marker
+            .strip_prefix("repo-map:start")
+            .ok_or_else(|| "expected `repo-map:start`".to_string())?;
+        if !rest.is_empty() && !rest.starts_with([':', ' ', '\t']) {
+            return Err("expected `repo-map:start:<name>`".to_string());
+        }
+        Ok(Self {
+            info: marker.to_string(),
+            format: BlockFormat::Markers(doc_format),
+            ..Self::parse_info(&format!("repo-map{rest}"))?
+        })
+    }
+
+    /// Renders `tree` in the same format as the block was written in. Only Markdown markers
+    /// can hold the clickable modes, linking entries to their path below `base`, so a legacy
+    /// block is rewritten between markers under its heading for them.
+    pub fn render(
+        &self,
+        tree: &FileTree,
+        limits: RenderLimits,
+        mode: RenderMode,
+        base: &str,
+    ) -> String {
+        match (self.format, mode) {
+            (BlockFormat::Heading, RenderMode::Code) => tree.render_block(&self.info, limits),
+            (BlockFormat::Heading, _) => {
+                let markers = self.to_markers().render(tree, limits, mode, base);
+                format!("# Repo map\n{markers}")
+            }
+            (BlockFormat::Markers(DocFormat::Markdown), RenderMode::List | RenderMode::Html) => {
+                let body = match mode {
+                    RenderMode::List => tree.render_list(limits, base),
+                    _ => format!("<pre>\n{}\n</pre>", tree.render_html(limits, base)),
+                };
+                format!("<!-- {} -->\n{body}\n<!-- repo-map:end -->", self.info)
+            }
+            (BlockFormat::Markers(doc_format), _) => {
+                doc_format.render_markers(&self.info, &tree.render_tree(limits))
+            }
+        }
+    }
+
+    /// The same block between Markdown markers, keeping its name and settings.
+    fn to_markers(&self) -> Self {
+        // `parse_info` only accepts info strings without a name or settings otherwise
+        let rest = self.info.strip_prefix("repo-map").unwrap_or_default();
+        Self {
+            info: format!("repo-map:start{rest}"),
+            format: BlockFormat::Markers(DocFormat::Markdown),
+            ..self.clone()
+        }
+    }
+
+    pub fn parse_info(info: &str) -> Result<Self, String> {
+        let mut words = info.split_whitespace();
+        let name = match words.next().and_then(|word| word.strip_prefix("repo-map")) {
+            Some("") => None,
+            Some(name) => match name.strip_prefix(':') {
+                Some(name) if !name.is_empty() => Some(name.to_string()),
+                _ => return Err("expected `repo-map:<name>`".to_string()),
+            },
+            // any other info string is a plain block from before names were supported
+            None => {
+                return Ok(Self {
+                    info: info.to_string(),
+                    ..Self::default()
+                })
+            }
+        };
+
+        let mut options = BlockOptions::default();
+        for word in words {
+            let Some((key, value)) = word.split_once('=') else {
+                return Err(format!("expected `key=value`, got `{word}`"));
+            };
+            match key {
+                "root" => options.root = Some(value.to_string()),
+                "allowed-exts" => {
+                    options.allowed_exts = Some(value.split(',').map(str::to_string).collect())
+                }
+                "dirs-only" => {
+                    options.dirs_only = Some(value.parse().map_err(|_| {
+                        format!("expected `true` or `false` for `dirs-only`, got `{value}`")
+                    })?)
+                }
+                "max-depth" | "max-children" => {
+                    let limit =
+                        Some(value.parse().map_err(|_| {
+                            format!("expected a number for `{key}`, got `{value}`")
+                        })?);
+                    match key {
+                        "max-depth" => options.max_depth = limit,
+                        _ => options.max_children = limit,
+                    }
+                }
+                // ignore settings decide what the walk enters, so they only apply to every block
+                _ => {
+                    return Err(format!(
+                        "unknown setting `{key}`, expected one of {BLOCK_SETTINGS}"
+                    ))
+                }
+            }
+        }
+        Ok(Self {
+            name,
+            options,
+            info: info.to_string(),
+            format: BlockFormat::Heading,
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Args, GitIgnore, ReadMe};
-    use crate::core::converters::{to_hashset, to_regex_vec, to_strings};
-    use regex::Regex;
-    use std::path::PathBuf;
+    use super::{
+        parse_git_excludes, parse_pattern, Args, BlockFormat, DocFormat, GitIgnore, ReadMe,
+        RepoMapBlock, DEFAULT_ALLOWED_EXTS, DEFAULT_HEADING, DEFAULT_IGNORE_DIRS,
+    };
+    use crate::core::adapters::FakeFileSystem;
+    use crate::core::config::{BlockOptions, Options};
+    use crate::core::converters::{to_hashset, to_strings};
+    use crate::core::domain::{FileTree, InsertPosition, RenderLimits, RenderMode, SymlinkMode};
+    use std::{
+        collections::{BTreeMap, HashMap},
+        path::{Path, PathBuf},
+    };
     use test_case::test_case;
 
     #[test]
     fn test_args() {
         let args = Args::new(
             "root".to_string(),
-            "readme.md".to_string(),
-            ".gitignore".to_string(),
-            to_strings(["py", "rs"]),
-            vec![],
-            true,
+            Options {
+                readme_path: Some("readme.md".to_string()),
+                gitignore_path: Some(".gitignore".to_string()),
+                allowed_exts: Some(to_strings(["py", "rs"])),
+                ignore_dirs: Some(vec![]),
+                ignore_hidden: Some(true),
+                ignore_files: Some(to_strings([".dockerignore"])),
+                symlinks: Some(SymlinkMode::Follow),
+                strict: Some(true),
+                heading: Some("Layout".to_string()),
+                heading_level: Some(2),
+                insert_position: Some(InsertPosition::Start),
+                max_depth: Some(2),
+                ..Options::default()
+            },
+            false,
             false,
         );
 
         let expected_result = Args {
             repo_root: PathBuf::from("root"),
-            readme_path: PathBuf::from("readme.md"),
-            gitignore_path: PathBuf::from(".gitignore"),
+            readme_path: Some(PathBuf::from("readme.md")),
+            gitignore_path: Some(PathBuf::from(".gitignore")),
             allowed_exts: to_hashset(vec!["py", "rs"]),
             ignore_dirs: to_hashset(Vec::<&str>::new()),
             ignore_hidden: true,
             dirs_only: false,
+            git_excludes: false,
+            ignore_files: to_strings([".dockerignore"]),
+            git_tracked: false,
+            symlinks: SymlinkMode::Follow,
+            strict: true,
+            heading: "Layout".to_string(),
+            heading_level: 2,
+            insert_position: InsertPosition::Start,
+            max_depth: Some(2),
+            max_children: None,
+            annotate: false,
+            metadata: vec![],
+            render: RenderMode::Code,
+            check: false,
+            stdout: false,
+            blocks: BTreeMap::new(),
         };
 
         assert_eq!(args, expected_result);
+        assert_eq!(args.section_heading(), Some("## Layout".to_string()));
+        assert_eq!(
+            args.block_format(),
+            BlockFormat::Markers(DocFormat::Markdown)
+        );
+    }
+
+    #[test]
+    fn test_args_defaults() {
+        let args = Args::new("root".to_string(), Options::default(), false, true);
+
+        let expected_result = Args {
+            repo_root: PathBuf::from("root"),
+            readme_path: None,
+            gitignore_path: None,
+            allowed_exts: to_hashset(DEFAULT_ALLOWED_EXTS),
+            ignore_dirs: to_hashset(DEFAULT_IGNORE_DIRS),
+            ignore_hidden: false,
+            dirs_only: false,
+            git_excludes: false,
+            ignore_files: vec![],
+            git_tracked: false,
+            symlinks: SymlinkMode::Plain,
+            strict: false,
+            heading: DEFAULT_HEADING.to_string(),
+            heading_level: 1,
+            insert_position: InsertPosition::End,
+            max_depth: None,
+            max_children: None,
+            annotate: false,
+            metadata: vec![],
+            render: RenderMode::Code,
+            check: false,
+            stdout: true,
+            blocks: BTreeMap::new(),
+        };
+
+        assert_eq!(args, expected_result);
+        assert_eq!(args.section_heading(), None);
+        assert_eq!(args.block_format(), BlockFormat::Heading);
+    }
+
+    #[test_case("root/README.md", false, "" ; "Ensure links start from a README in the repo root")]
+    #[test_case("root/docs/api/README.md", false, "../.." ; "Ensure links go up from a README in a subdirectory")]
+    #[test_case("root/docs/README.md", true, "" ; "Ensure links start from the repo root on stdout")]
+    fn test_args_link_base(readme_path: &str, stdout: bool, expected_result: &str) {
+        let args = Args::new(
+            "root".to_string(),
+            Options {
+                readme_path: Some(readme_path.to_string()),
+                ..Options::default()
+            },
+            false,
+            stdout,
+        );
+
+        assert_eq!(args.link_base(), expected_result);
     }
 
     #[test]
     fn test_gitignore() {
-        fn regex_vec_to_strs(vec: &[Regex]) -> Vec<&str> {
-            vec.iter().map(|re| re.as_str()).collect()
-        }
+        let gitignore = GitIgnore(
+            ".pytest_cache/\n*.log\n?scratch.py\n/outputs/\n!keep.log\nsrc/*.rs\n**/build\ndocs/**/generated\nlogs/**"
+                .to_string(),
+        );
+
+        let rules = gitignore.parse_lines();
+        let actual_result: Vec<(&str, bool, bool)> = rules
+            .patterns
+            .iter()
+            .map(|p| (p.regex.as_str(), p.negated, p.dir_only))
+            .collect();
+        let expected_result = vec![
+            ("(^|/)\\.pytest_cache$", false, true),
+            ("(^|/)[^/]*\\.log$", false, false),
+            ("(^|/)[^/]scratch\\.py$", false, false),
+            ("^outputs$", false, true),
+            ("(^|/)keep\\.log$", true, false),
+            ("^src/[^/]*\\.rs$", false, false),
+            ("^(.*/)?build$", false, false),
+            ("^docs/(.*/)?generated$", false, false),
+            ("^logs/.*$", false, false),
+        ];
 
-        let gitignore = GitIgnore(".pytest_cache/\n*.log\n?scratch.py\n/outputs/".to_string());
+        assert_eq!(actual_result, expected_result);
+    }
 
-        let actual_result = gitignore.parse_lines();
-        let expected_result = to_regex_vec(vec![
-            "(^|/)\\.pytest_cache/(.*)?$",
-            "(^|/)[^/]*\\.log$",
-            "(^|/).scratch\\.py$",
-            "(^|/)outputs/(.*)?$",
-        ]);
+    #[test_case("a+b(c){1}$^.txt", "a+b(c){1}$^.txt", true ; "Ensure regex metacharacters are matched literally")]
+    #[test_case("a+b(c){1}$^.txt", "aab(c){1}$^.txt", false ; "Ensure plus is not a regex quantifier")]
+    #[test_case("[abc].txt", "b.txt", true ; "Ensure bracket class matches a listed char")]
+    #[test_case("[abc].txt", "d.txt", false ; "Ensure bracket class rejects an unlisted char")]
+    #[test_case("file[!0-9].log", "filex.log", true ; "Ensure negated range matches outside the range")]
+    #[test_case("file[!0-9].log", "file1.log", false ; "Ensure negated range rejects inside the range")]
+    #[test_case("file[^0-9].log", "dir/file/.log", false ; "Ensure negated class never matches a slash")]
+    #[test_case("[]]x", "]x", true ; "Ensure leading closing bracket is a literal")]
+    #[test_case("[[:digit:]]x", "7x", true ; "Ensure POSIX classes are supported")]
+    #[test_case("\\#notes", "#notes", true ; "Ensure escaped hash is a literal")]
+    #[test_case("\\!important", "!important", true ; "Ensure escaped exclamation mark is a literal")]
+    #[test_case("trailing\\ ", "trailing ", true ; "Ensure escaped trailing space is kept")]
+    #[test_case("trailing  ", "trailing", true ; "Ensure unescaped trailing spaces are stripped")]
+    #[test_case("?.md", "a/.md", false ; "Ensure question mark does not match a slash")]
+    fn test_parse_pattern(pattern: &str, path: &str, expected_result: bool) {
+        let pattern = parse_pattern(pattern).unwrap().unwrap();
+        assert_eq!(pattern.is_match(Path::new(path), false), expected_result);
+    }
 
-        assert_eq!(
-            regex_vec_to_strs(&actual_result),
-            regex_vec_to_strs(&expected_result)
+    #[test_case("[abc", "unclosed character class" ; "Ensure unclosed class is reported")]
+    #[test_case("abc\\", "trailing backslash" ; "Ensure trailing backslash is reported")]
+    #[test_case("[z-a]", "invalid character class range" ; "Ensure invalid range is reported")]
+    fn test_parse_pattern_errors(pattern: &str, expected_result: &str) {
+        assert!(parse_pattern(pattern)
+            .unwrap_err()
+            .contains(expected_result));
+    }
+
+    #[test_case("# comment" ; "Ensure comments are skipped")]
+    #[test_case("   " ; "Ensure blank lines are skipped")]
+    fn test_parse_pattern_no_pattern(pattern: &str) {
+        assert!(!matches!(parse_pattern(pattern), Ok(Some(_))));
+    }
+
+    #[test_case(
+        vec![],
+        vec![("repo/app.log", false), ("repo/scratch.txt", false)] ;
+        "Ensure nothing is excluded without exclude files"
+    )]
+    #[test_case(
+        vec![("repo/.git/info/exclude", "*.log\n")],
+        vec![("repo/app.log", true), ("repo/scratch.txt", false)] ;
+        "Ensure reads .git/info/exclude"
+    )]
+    #[test_case(
+        vec![("home/.config/git/ignore", "*.txt\n")],
+        vec![("repo/app.log", false), ("repo/scratch.txt", true)] ;
+        "Ensure falls back to the XDG ignore file"
+    )]
+    #[test_case(
+        vec![
+            ("home/.gitconfig", "[user]\n\tname = me\n[core]\n\texcludesFile = ~/.global_ignore ; comment\n"),
+            ("home/.global_ignore", "*.txt\n"),
+            ("home/.config/git/ignore", "*.log\n"),
+        ],
+        vec![("repo/app.log", false), ("repo/scratch.txt", true)] ;
+        "Ensure core.excludesFile replaces the XDG ignore file"
+    )]
+    #[test_case(
+        vec![
+            ("home/.gitconfig", "[core]\n\texcludesfile = \"~/global_ignore\"\n"),
+            ("repo/.git/config", "[core]\n\texcludesFile = local_ignore\n"),
+            ("home/global_ignore", "*.txt\n"),
+            ("repo/local_ignore", "*.log\n"),
+        ],
+        vec![("repo/app.log", true), ("repo/scratch.txt", false)] ;
+        "Ensure the repo config overrides the global config"
+    )]
+    #[test_case(
+        vec![
+            ("home/.gitconfig", "[core]\n\texcludesFile = ignores/global\n"),
+            ("repo/ignores/global", "*.txt\n"),
+            ("ignores/global", "*.log\n"),
+        ],
+        vec![("repo/app.log", false), ("repo/scratch.txt", true)] ;
+        "Ensure a relative core.excludesFile is relative to the repo root"
+    )]
+    #[test_case(
+        vec![
+            ("home/.config/git/ignore", "*.log\n"),
+            ("repo/.git/info/exclude", "!app.log\n"),
+        ],
+        vec![("repo/app.log", false)] ;
+        "Ensure .git/info/exclude takes precedence over the global excludes"
+    )]
+    fn test_parse_git_excludes(files: Vec<(&str, &str)>, expected_result: Vec<(&str, bool)>) {
+        let mut file_sys = FakeFileSystem::new(
+            files
+                .into_iter()
+                .map(|(k, v)| (PathBuf::from(k), v.to_string()))
+                .collect::<HashMap<PathBuf, String>>(),
+        );
+
+        let rules = parse_git_excludes(
+            &mut file_sys,
+            Path::new("repo"),
+            Some(Path::new("home")),
+            None,
         );
+
+        for (path, ignored) in expected_result {
+            let rel_path = Path::new(path).strip_prefix("repo").unwrap();
+            assert_eq!(rules.is_ignored(rel_path, false), ignored, "{path}");
+        }
     }
 
     #[test_case(
@@ -235,10 +1354,333 @@ mod tests {
         "Ensure replaces if the repo map exists"
     )]
     fn test_readme_if_not_already_exists(inp_readme: &str, repo_map: &str, expected_result: &str) {
-        let readme = ReadMe(inp_readme.into());
+        let readme = ReadMe(inp_readme.into(), DocFormat::Markdown);
         assert_eq!(
-            readme.update_readme(repo_map.into()),
-            ReadMe(expected_result.into()),
+            readme.update_readme(
+                &[(RepoMapBlock::default(), repo_map.into())],
+                None,
+                &InsertPosition::End
+            ),
+            ReadMe(expected_result.into(), DocFormat::Markdown),
+        );
+    }
+
+    #[test]
+    fn test_readme_named_blocks() {
+        let readme = ReadMe(
+            "# Some readme\n# Repo map\n```repo-map:src root=src\nold src\n::\n```\ntext\n# Repo map\n```\nold default\n::\n```"
+                .into(),
+            DocFormat::Markdown,
         );
+        let src = RepoMapBlock::parse_info("repo-map:src root=src").unwrap();
+
+        let actual_result = readme.update_readme(
+            &[
+                (
+                    src,
+                    "# Repo map\n```repo-map:src root=src\nnew src\n::\n```".into(),
+                ),
+                (
+                    RepoMapBlock::new(Some("overview"), BlockFormat::Heading),
+                    "# Repo map\n```repo-map:overview\nnew overview\n::\n```".into(),
+                ),
+            ],
+            None,
+            &InsertPosition::End,
+        );
+
+        let expected_result = ReadMe(
+            "# Some readme\n# Repo map\n```repo-map:src root=src\nnew src\n::\n```\ntext\n# Repo map\n```\nold default\n::\n```\n\n# Repo map\n```repo-map:overview\nnew overview\n::\n```"
+                .into(),
+            DocFormat::Markdown,
+        );
+        assert_eq!(actual_result, expected_result);
+    }
+
+    #[test_case(
+        "## Layout\n<!-- repo-map:start -->\n```\noriginal\n```\n<!-- repo-map:end -->\nafterwards",
+        "## Layout\n<!-- repo-map:start -->\n```\n└── a.py\n```\n<!-- repo-map:end -->\nafterwards" ;
+        "Ensure replaces the content between the markers"
+    )]
+    #[test_case(
+        "<!--repo-map:start:src root=src-->\nanything\n\n<!--  repo-map:end  -->",
+        "<!-- repo-map:start:src root=src -->\n```\n└── a.py\n```\n<!-- repo-map:end -->" ;
+        "Ensure tolerates spacing inside the markers"
+    )]
+    #[test_case(
+        "<!-- repo-map:start -->\n# Repo map\n```\noriginal\n::\n```\n<!-- repo-map:end -->",
+        "<!-- repo-map:start -->\n```\n└── a.py\n```\n<!-- repo-map:end -->" ;
+        "Ensure a legacy block wrapped in markers is replaced as one"
+    )]
+    #[test_case(
+        "````markdown\n<!-- repo-map:start -->\n```\nexample\n```\n<!-- repo-map:end -->\n````\n<!-- repo-map:start -->\n<!-- repo-map:end -->",
+        "````markdown\n<!-- repo-map:start -->\n```\nexample\n```\n<!-- repo-map:end -->\n````\n<!-- repo-map:start -->\n```\n└── a.py\n```\n<!-- repo-map:end -->" ;
+        "Ensure examples of markers in fenced code are left as is"
+    )]
+    #[test_case(
+        "~~~\n# Repo map\n```\nexample\n::\n```\n~~~\n",
+        "~~~\n# Repo map\n```\nexample\n::\n```\n~~~\n" ;
+        "Ensure examples of legacy blocks in fenced code are left as is"
+    )]
+    fn test_readme_markers(inp_readme: &str, expected_result: &str) {
+        let readme = ReadMe(inp_readme.into(), DocFormat::Markdown);
+        let tree = FileTree::new().create_map(vec![PathBuf::from("a.py")]);
+
+        let repo_maps: Vec<(RepoMapBlock, String)> = readme
+            .blocks()
+            .unwrap()
+            .into_iter()
+            .map(|block| {
+                let repo_map = block.render(&tree, RenderLimits::default(), RenderMode::Code, "");
+                (block, repo_map)
+            })
+            .collect();
+
+        assert_eq!(
+            readme.update_readme(&repo_maps, None, &InsertPosition::End),
+            ReadMe(expected_result.into(), DocFormat::Markdown)
+        );
+    }
+
+    #[test_case(
+        "# Title\ntext\n",
+        InsertPosition::End,
+        "# Title\ntext\n\n## Layout\n<!-- repo-map:start -->\nmap\n<!-- repo-map:end -->" ;
+        "Ensure appends the section at the end"
+    )]
+    #[test_case(
+        "# Title\ntext",
+        InsertPosition::Start,
+        "## Layout\n<!-- repo-map:start -->\nmap\n<!-- repo-map:end -->\n\n# Title\ntext" ;
+        "Ensure inserts the section at the start"
+    )]
+    #[test_case(
+        "# Title\n## Install\n```shell\n# not a heading\n```\n### Extras\ntext\n\n## Usage\ntext",
+        InsertPosition::After("Install".to_string()),
+        "# Title\n## Install\n```shell\n# not a heading\n```\n### Extras\ntext\n\n## Layout\n<!-- repo-map:start -->\nmap\n<!-- repo-map:end -->\n\n## Usage\ntext" ;
+        "Ensure inserts the section at the end of the heading's section"
+    )]
+    #[test_case(
+        "# Title\n## Install\ntext",
+        InsertPosition::After("## Install".to_string()),
+        "# Title\n## Install\ntext\n\n## Layout\n<!-- repo-map:start -->\nmap\n<!-- repo-map:end -->" ;
+        "Ensure inserts the section after a heading that ends the README"
+    )]
+    #[test_case(
+        "# Title\ntext",
+        InsertPosition::After("Missing".to_string()),
+        "# Title\ntext\n\n## Layout\n<!-- repo-map:start -->\nmap\n<!-- repo-map:end -->" ;
+        "Ensure appends the section if the heading is missing"
+    )]
+    fn test_readme_insert_position(
+        inp_readme: &str,
+        position: InsertPosition,
+        expected_result: &str,
+    ) {
+        let readme = ReadMe(inp_readme.into(), DocFormat::Markdown);
+        let block = RepoMapBlock::new(None, BlockFormat::Markers(DocFormat::Markdown));
+
+        assert_eq!(
+            readme.update_readme(
+                &[(
+                    block,
+                    "<!-- repo-map:start -->\nmap\n<!-- repo-map:end -->".into()
+                )],
+                Some("## Layout"),
+                &position
+            ),
+            ReadMe(expected_result.into(), DocFormat::Markdown)
+        );
+    }
+
+    #[test_case("docs/architecture.md", DocFormat::Markdown ; "Ensure markdown files are markdown")]
+    #[test_case("CONTRIBUTING", DocFormat::Markdown ; "Ensure files without an extension are markdown")]
+    #[test_case("README.rst", DocFormat::ReStructuredText ; "Ensure rst files are reStructuredText")]
+    #[test_case("docs/index.ADOC", DocFormat::AsciiDoc ; "Ensure adoc files are AsciiDoc")]
+    fn test_doc_format_from_path(path: &str, expected_result: DocFormat) {
+        assert_eq!(DocFormat::from_path(Path::new(path)), expected_result);
+    }
+
+    #[test_case(
+        DocFormat::ReStructuredText,
+        "Title\n=====\n\n.. repo-map:start\n\n.. code-block:: text\n\n   old\n\n.. repo-map:end\n\nMore",
+        "Title\n=====\n\n.. repo-map:start\n\n.. code-block:: text\n\n   ├── src\n   │   └── lib.rs\n   └── a.py\n\n.. repo-map:end\n\nMore" ;
+        "Ensure replaces the block between reStructuredText comments"
+    )]
+    #[test_case(
+        DocFormat::AsciiDoc,
+        "= Title\n\n// repo-map:start:src root=src\n----\nold\n----\n// repo-map:end\n\nMore",
+        "= Title\n\n// repo-map:start:src root=src\n----\n├── src\n│   └── lib.rs\n└── a.py\n----\n// repo-map:end\n\nMore" ;
+        "Ensure replaces the block between AsciiDoc comments"
+    )]
+    #[test_case(
+        DocFormat::ReStructuredText,
+        "Title\n=====\n\n# Repo map\n```\nold\n::\n```",
+        "Title\n=====\n\n# Repo map\n```\nold\n::\n```" ;
+        "Ensure the legacy markdown block is only found in markdown"
+    )]
+    fn test_readme_doc_formats(doc_format: DocFormat, inp_readme: &str, expected_result: &str) {
+        let readme = ReadMe(inp_readme.into(), doc_format);
+        let tree =
+            FileTree::new().create_map(vec![PathBuf::from("src/lib.rs"), PathBuf::from("a.py")]);
+
+        let repo_maps: Vec<(RepoMapBlock, String)> = readme
+            .blocks()
+            .unwrap()
+            .into_iter()
+            .map(|block| {
+                let repo_map = block.render(&tree, RenderLimits::default(), RenderMode::Code, "");
+                (block, repo_map)
+            })
+            .collect();
+
+        assert_eq!(
+            readme
+                .update_readme(&repo_maps, None, &InsertPosition::End)
+                .0,
+            expected_result
+        );
+    }
+
+    #[test_case(
+        DocFormat::ReStructuredText,
+        "=====\nTitle\n=====\n\nInstall\n-------\ntext\n\nExtras\n~~~~~~\n::\n\n   Not\n   ---\n\nUsage\n-----\ntext",
+        "Install",
+        Some("Usage\n-----\ntext") ;
+        "Ensure finds the end of a reStructuredText section"
+    )]
+    #[test_case(
+        DocFormat::AsciiDoc,
+        "= Title\n\n== Install\n----\n== not a heading\n----\n=== Extras\n\n== Usage\ntext",
+        "Install",
+        Some("== Usage\ntext") ;
+        "Ensure finds the end of an AsciiDoc section"
+    )]
+    #[test_case(
+        DocFormat::AsciiDoc,
+        "= Title\n\n== Install\ntext",
+        "== Install",
+        Some("") ;
+        "Ensure the last section ends with the text"
+    )]
+    #[test_case(DocFormat::Markdown, "# Title\ntext", "Install", None ; "Ensure a missing heading has no section")]
+    #[test_case(
+        DocFormat::Markdown,
+        "# Title\n## Install\n````markdown\n```\n## not a heading\n```\n````\n## Usage",
+        "Install",
+        Some("## Usage") ;
+        "Ensure skips headings in nested fences"
+    )]
+    fn test_end_of_section(
+        doc_format: DocFormat,
+        text: &str,
+        heading: &str,
+        expected_result: Option<&str>,
+    ) {
+        let actual_result = doc_format
+            .end_of_section(text, heading)
+            .map(|end| &text[end..]);
+        assert_eq!(actual_result, expected_result);
+    }
+
+    #[test_case(DocFormat::Markdown, "## Layout" ; "Ensure markdown headings are hashes")]
+    #[test_case(DocFormat::ReStructuredText, "Layout\n------\n" ; "Ensure reStructuredText headings are underlined")]
+    #[test_case(DocFormat::AsciiDoc, "== Layout" ; "Ensure AsciiDoc headings are equals signs")]
+    fn test_doc_format_heading(doc_format: DocFormat, expected_result: &str) {
+        assert_eq!(doc_format.heading("Layout", 2), expected_result);
+    }
+
+    #[test_case("repo-map:start", Ok((None, BlockOptions::default())) ; "Ensure a bare start marker is the default block")]
+    #[test_case(
+        "repo-map:start dirs-only=true",
+        Ok((None, BlockOptions { dirs_only: Some(true), ..BlockOptions::default() })) ;
+        "Ensure parses settings of the default block"
+    )]
+    #[test_case(
+        "repo-map:start:src root=src",
+        Ok((Some("src"), BlockOptions { root: Some("src".to_string()), ..BlockOptions::default() })) ;
+        "Ensure parses the name and settings of a marker"
+    )]
+    #[test_case("repo-map:started", Err(()) ; "Ensure other markers are rejected")]
+    #[test_case("repo-map:start: root=src", Err(()) ; "Ensure an empty marker name is rejected")]
+    fn test_parse_block_marker(
+        marker: &str,
+        expected_result: Result<(Option<&str>, BlockOptions), ()>,
+    ) {
+        let actual_result = RepoMapBlock::parse_marker(marker, DocFormat::Markdown)
+            .map(|block| {
+                assert_eq!(block.format, BlockFormat::Markers(DocFormat::Markdown));
+                (block.name, block.options)
+            })
+            .map_err(|_| ());
+        let expected_result =
+            expected_result.map(|(name, options)| (name.map(str::to_string), options));
+
+        assert_eq!(actual_result, expected_result);
+    }
+
+    #[test_case("", Ok((None, BlockOptions::default())) ; "Ensure a plain fence is the default block")]
+    #[test_case("repo-map", Ok((None, BlockOptions::default())) ; "Ensure an unnamed marker is the default block")]
+    #[test_case(
+        "repo-map:src root=src allowed-exts=py,rs dirs-only=true max-depth=2 max-children=10",
+        Ok((Some("src"), BlockOptions {
+            root: Some("src".to_string()),
+            allowed_exts: Some(to_strings(["py", "rs"])),
+            dirs_only: Some(true),
+            max_depth: Some(2),
+            max_children: Some(10),
+        })) ;
+        "Ensure parses the name and settings"
+    )]
+    #[test_case("repo-map: root=src", Err(()) ; "Ensure an empty name is rejected")]
+    #[test_case("repo-map:src depth", Err(()) ; "Ensure a setting without a value is rejected")]
+    #[test_case("repo-map:src colour=red", Err(()) ; "Ensure unknown settings are rejected")]
+    #[test_case("repo-map:src ignore-dirs=tests", Err(()) ; "Ensure global ignore settings are rejected")]
+    #[test_case("repo-map:src dirs-only=yes", Err(()) ; "Ensure invalid values are rejected")]
+    #[test_case("repo-map:src max-depth=-1", Err(()) ; "Ensure invalid limits are rejected")]
+    fn test_parse_block_info(
+        info: &str,
+        expected_result: Result<(Option<&str>, BlockOptions), ()>,
+    ) {
+        let actual_result = RepoMapBlock::parse_info(info)
+            .map(|block| (block.name, block.options))
+            .map_err(|_| ());
+        let expected_result =
+            expected_result.map(|(name, options)| (name.map(str::to_string), options));
+
+        assert_eq!(actual_result, expected_result);
+    }
+
+    #[test_case(
+        "# Some readme\n# Repo map\n```\n├── a.py\n└── b.py\n::\n```",
+        "-├── a.py\n-└── b.py\n+├── b.py\n+└── c.py\n" ;
+        "Ensure diffs the existing repo map"
+    )]
+    #[test_case(
+        "# Some readme",
+        "+# Repo map\n+```\n+├── b.py\n+└── c.py\n+::\n+```\n" ;
+        "Ensure diffs against nothing if there is no repo map"
+    )]
+    fn test_diff_repo_map(inp_readme: &str, expected_changes: &str) {
+        let readme = ReadMe(inp_readme.into(), DocFormat::Markdown);
+        let updated = readme.update_readme(
+            &[(
+                RepoMapBlock::default(),
+                "# Repo map\n```\n├── b.py\n└── c.py\n::\n```".into(),
+            )],
+            None,
+            &InsertPosition::End,
+        );
+
+        let actual_changes: String = readme
+            .diff_repo_map(&updated)
+            .lines()
+            .filter(|line| {
+                line.starts_with(['+', '-']) && !line.starts_with("+++") && !line.starts_with("---")
+            })
+            .map(|line| format!("{line}\n"))
+            .collect();
+
+        assert_eq!(actual_changes, expected_changes);
     }
 }
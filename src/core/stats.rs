@@ -0,0 +1,95 @@
+use crate::core::{
+    adapters::FileSystem,
+    domain::{FileStats, MetadataColumn},
+};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// Reads the `columns` of metadata of every file in `paths`, relative to `repo_root`. Files
+/// that can't be read are left out. Only counting lines reads the files themselves.
+pub fn read_stats(
+    file_sys: &mut impl FileSystem,
+    repo_root: &Path,
+    paths: &[PathBuf],
+    columns: &[MetadataColumn],
+) -> HashMap<PathBuf, FileStats> {
+    let size = columns.contains(&MetadataColumn::Size);
+    let lines = columns.contains(&MetadataColumn::Lines);
+    let modified = columns.contains(&MetadataColumn::Modified);
+
+    paths
+        .iter()
+        .filter_map(|path| {
+            let full_path = repo_root.join(path);
+            let metadata = if size || modified {
+                Some(file_sys.metadata(&full_path).ok()?)
+            } else {
+                None
+            };
+            let lines = if lines {
+                Some(file_sys.count_lines(&full_path).ok()?)
+            } else {
+                None
+            };
+            let stats = FileStats {
+                size: metadata.filter(|_| size).map(|metadata| metadata.size),
+                lines,
+                modified: metadata
+                    .filter(|_| modified)
+                    .and_then(|metadata| metadata.modified),
+            };
+            Some((path.clone(), stats))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_stats;
+    use crate::core::{
+        adapters::FakeFileSystem,
+        domain::{FileStats, MetadataColumn},
+    };
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+        time::{Duration, UNIX_EPOCH},
+    };
+    use test_case::test_case;
+
+    #[test_case(&[MetadataColumn::Size, MetadataColumn::Lines, MetadataColumn::Modified], Some(11), Some(2), true ; "Ensure reads every column")]
+    #[test_case(&[MetadataColumn::Lines], None, Some(2), false ; "Ensure reads only the requested columns")]
+    fn test_read_stats(
+        columns: &[MetadataColumn],
+        size: Option<u64>,
+        lines: Option<usize>,
+        modified: bool,
+    ) {
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut file_sys = FakeFileSystem::new(HashMap::from([(
+            PathBuf::from("repo/src/lib.rs"),
+            "use std;\nfn".to_string(),
+        )]));
+        file_sys
+            .modified
+            .insert(PathBuf::from("repo/src/lib.rs"), time);
+
+        let paths = vec![PathBuf::from("src/lib.rs"), PathBuf::from("src/missing.rs")];
+        let actual_result = read_stats(&mut file_sys, Path::new("repo"), &paths, columns);
+
+        let expected_stats = FileStats {
+            size,
+            lines,
+            modified: modified.then_some(time),
+        };
+        assert_eq!(
+            actual_result,
+            HashMap::from([(PathBuf::from("src/lib.rs"), expected_stats)])
+        );
+        if !columns.contains(&MetadataColumn::Lines) {
+            assert!(!file_sys.operations.iter().any(|op| op.starts_with("read")));
+        }
+    }
+}
@@ -0,0 +1,455 @@
+use crate::core::{
+    adapters::{DirEntry, DirReader, FileSystem},
+    domain::{IgnoreLayer, SymlinkMode},
+    parsing::read_ignore_file,
+};
+use colored::Colorize;
+use rayon::{prelude::*, Scope};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError, RwLock},
+};
+
+#[derive(Debug, Default)]
+pub struct Walk {
+    pub paths: Vec<PathBuf>,
+    /// Targets of the symlinks in `paths` mapped as leaves, keyed relative to the root.
+    pub links: HashMap<PathBuf, PathBuf>,
+    /// Everything that couldn't be read, so the paths may be incomplete.
+    pub errors: Vec<WalkError>,
+}
+
+impl Walk {
+    fn extend(&mut self, other: Walk) {
+        self.paths.extend(other.paths);
+        self.links.extend(other.links);
+        self.errors.extend(other.errors);
+    }
+}
+
+#[derive(Debug)]
+pub enum WalkErrorKind {
+    /// The directory couldn't be listed, nothing below it is mapped.
+    ReadDir,
+    /// An entry of the directory couldn't be read, it is left out.
+    ReadEntry,
+    /// The symlinked directory couldn't be resolved, it is mapped as a leaf.
+    ResolveSymlink,
+    /// The ignore file couldn't be read, its rules are not applied.
+    ReadIgnoreFile,
+}
+
+#[derive(Debug)]
+pub struct WalkError {
+    pub path: PathBuf,
+    pub kind: WalkErrorKind,
+    pub error: io::Error,
+}
+
+impl fmt::Display for WalkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.kind {
+            WalkErrorKind::ReadDir => "Failed to list",
+            WalkErrorKind::ReadEntry => "Failed to read an entry of",
+            WalkErrorKind::ResolveSymlink => "Failed to resolve symlink",
+            WalkErrorKind::ReadIgnoreFile => "Failed to read ignore file",
+        };
+        write!(f, "{action} `{}` ({})", self.path.display(), self.error)
+    }
+}
+
+/// A directory waiting to be read.
+struct Dir {
+    path: PathBuf,
+    /// Canonical parents of the symlinks followed to get here, used to detect loops.
+    followed: Vec<PathBuf>,
+}
+
+/// What every task of a walk shares.
+struct Walker<'a, R> {
+    reader: R,
+    root: &'a Path,
+    ignore_dirs: &'a HashSet<String>,
+    ignore_hidden: bool,
+    symlinks: SymlinkMode,
+    layers: RwLock<&'a mut [IgnoreLayer]>,
+    walked: Mutex<Walk>,
+}
+
+/// Lists the files below `root` without entering ignored directories, sorted so the result
+/// is the same however the directories were read. Each directory is read in its own task on
+/// rayon's work-stealing pool as soon as its parent has been, so a slow directory only holds
+/// up what is below it. Its ignore files are read into `layers` before its subdirectories
+/// are queued, so they apply to everything below it.
+pub fn walk(
+    file_sys: &mut impl FileSystem,
+    root: &Path,
+    ignore_dirs: &HashSet<String>,
+    ignore_hidden: bool,
+    symlinks: SymlinkMode,
+    layers: &mut [IgnoreLayer],
+) -> Walk {
+    let walker = Walker {
+        reader: file_sys.dir_reader(),
+        root,
+        ignore_dirs,
+        ignore_hidden,
+        symlinks,
+        layers: RwLock::new(layers),
+        walked: Mutex::new(Walk::default()),
+    };
+    let dir = Dir {
+        path: root.to_path_buf(),
+        followed: Vec::new(),
+    };
+    rayon::scope(|scope| walker.visit(scope, dir));
+
+    let mut walked = walker
+        .walked
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner);
+    walked.paths.par_sort();
+    walked.errors.sort_by(|a, b| a.path.cmp(&b.path));
+    walked
+}
+
+impl<R: DirReader> Walker<'_, R> {
+    /// Reads `dir` and queues each directory in it as a new task.
+    fn visit<'s>(&'s self, scope: &Scope<'s>, dir: Dir) {
+        let mut walked = Walk::default();
+        let entries = match self.reader.read_dir(&dir.path) {
+            Ok(entries) => entries,
+            Err(error) => {
+                walked.errors.push(WalkError {
+                    path: dir.path,
+                    kind: WalkErrorKind::ReadDir,
+                    error,
+                });
+                return self.merge(walked);
+            }
+        };
+        let entries: Vec<DirEntry> = entries
+            .into_iter()
+            .filter_map(|entry| {
+                entry
+                    .map_err(|error| {
+                        walked.errors.push(WalkError {
+                            path: dir.path.clone(),
+                            kind: WalkErrorKind::ReadEntry,
+                            error,
+                        })
+                    })
+                    .ok()
+            })
+            .collect();
+        self.read_ignore_files(&dir.path, &entries, &mut walked);
+
+        let entries: Vec<DirEntry> = {
+            let layers = self.layers.read().unwrap_or_else(PoisonError::into_inner);
+            entries
+                .into_iter()
+                .filter_map(|mut entry| match (&entry.link, self.symlinks) {
+                    (Some(_), SymlinkMode::Skip) => None,
+                    (Some(_), SymlinkMode::Plain | SymlinkMode::List) => {
+                        entry.is_dir = false;
+                        Some(entry)
+                    }
+                    _ => Some(entry),
+                })
+                .filter(|entry| !self.is_excluded(&entry.path, entry.is_dir, &layers))
+                .collect()
+        };
+
+        for entry in entries {
+            if !entry.is_dir {
+                if let (Some(target), SymlinkMode::List) = (entry.link, self.symlinks) {
+                    if let Ok(rel) = entry.path.strip_prefix(self.root) {
+                        walked.links.insert(rel.to_path_buf(), target);
+                    }
+                }
+                walked.paths.push(entry.path);
+                continue;
+            }
+            let Some(target) = entry.link else {
+                let child = Dir {
+                    path: entry.path,
+                    followed: dir.followed.clone(),
+                };
+                scope.spawn(move |scope| self.visit(scope, child));
+                continue;
+            };
+            match self.follow(&dir, &entry.path) {
+                Ok(Some(followed)) => {
+                    let child = Dir {
+                        path: entry.path,
+                        followed,
+                    };
+                    scope.spawn(move |scope| self.visit(scope, child));
+                }
+                followed => {
+                    match followed {
+                        Err(error) => walked.errors.push(WalkError {
+                            path: entry.path.clone(),
+                            kind: WalkErrorKind::ResolveSymlink,
+                            error,
+                        }),
+                        _ => eprintln!(
+                            "{} `{}`",
+                            "Not following symlink that loops:".yellow().bold(),
+                            entry.path.display()
+                        ),
+                    }
+                    if let Ok(rel) = entry.path.strip_prefix(self.root) {
+                        walked.links.insert(rel.to_path_buf(), target);
+                    }
+                    walked.paths.push(entry.path);
+                }
+            }
+        }
+        self.merge(walked);
+    }
+
+    /// Reads the ignore files listed in `dir` into their layers. The files are read without
+    /// holding the lock, only adding their rules blocks the other tasks.
+    fn read_ignore_files(&self, dir: &Path, entries: &[DirEntry], walked: &mut Walk) {
+        let Ok(base) = dir.strip_prefix(self.root) else {
+            return;
+        };
+        let paths: Vec<(usize, PathBuf)> = {
+            let layers = self.layers.read().unwrap_or_else(PoisonError::into_inner);
+            layers
+                .iter()
+                .enumerate()
+                .filter(|(_, layer)| layer.nested)
+                .filter_map(|(i, layer)| {
+                    let path = dir.join(&layer.filename);
+                    let listed = entries
+                        .iter()
+                        .any(|entry| !entry.is_dir && entry.path == path);
+                    let loaded = layer.loaded.as_ref() == Some(&path);
+                    (listed && !loaded).then_some((i, path))
+                })
+                .collect()
+        };
+        for (i, path) in paths {
+            match read_ignore_file(&self.reader, &path) {
+                Ok(Some(rules)) => {
+                    let mut layers = self.layers.write().unwrap_or_else(PoisonError::into_inner);
+                    layers[i].rules.extend(base, rules);
+                }
+                Ok(None) => {}
+                Err(error) => walked.errors.push(WalkError {
+                    path,
+                    kind: WalkErrorKind::ReadIgnoreFile,
+                    error,
+                }),
+            }
+        }
+    }
+
+    fn is_excluded(&self, path: &Path, is_dir: bool, layers: &[IgnoreLayer]) -> bool {
+        let Some(name) = path.file_name().and_then(|s| s.to_str()) else {
+            return true;
+        };
+        // only hidden files are dropped, the contents of hidden directories are still mapped
+        if (self.ignore_hidden && !is_dir && name.starts_with('.'))
+            || self.ignore_dirs.contains(name)
+        {
+            return true;
+        }
+        match path.strip_prefix(self.root) {
+            Ok(rel) => layers
+                .iter()
+                .any(|layer| layer.rules.is_ignored(rel, is_dir)),
+            Err(_) => true,
+        }
+    }
+
+    /// The followed links of a directory entered through `link`, `None` if that would loop
+    /// back into a directory the walk is already inside.
+    fn follow(&self, dir: &Dir, link: &Path) -> io::Result<Option<Vec<PathBuf>>> {
+        let parent = self.reader.canonicalize(&dir.path)?;
+        let target = self.reader.canonicalize(link)?;
+        if dir
+            .followed
+            .iter()
+            .chain([&parent])
+            .any(|anc| anc.starts_with(&target))
+        {
+            return Ok(None);
+        }
+        let mut followed = dir.followed.clone();
+        followed.push(parent);
+        Ok(Some(followed))
+    }
+
+    /// Adds what one task found to the walk.
+    fn merge(&self, walked: Walk) {
+        self.walked
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend(walked);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::walk;
+    use crate::core::{
+        adapters::FakeFileSystem,
+        converters::{to_hashset, to_pathbufs},
+        domain::{IgnoreLayer, SymlinkMode},
+        test_utils::get_mock_repo_vec,
+    };
+    use std::{collections::HashMap, path::PathBuf};
+    use test_case::test_case;
+
+    #[test]
+    fn test_walk_prunes_ignored_dirs() {
+        let mut files = get_mock_repo_vec()
+            .into_iter()
+            .map(|p| (PathBuf::from(p), String::new()))
+            .collect::<HashMap<PathBuf, String>>();
+        files.insert(
+            PathBuf::from("user/root/repo/.gitignore"),
+            "target/\n*.toml\n!Cargo.toml\n".to_string(),
+        );
+        let mut file_sys = FakeFileSystem::new(files);
+        let mut layers = vec![IgnoreLayer::new(".gitignore")];
+
+        let actual_result = walk(
+            &mut file_sys,
+            &PathBuf::from("user/root/repo"),
+            &to_hashset(vec![".venv", ".pytest_cache"]),
+            true,
+            SymlinkMode::List,
+            &mut layers,
+        )
+        .paths;
+
+        let expected_result = to_pathbufs(vec![
+            "user/root/repo/.github/workflows/ci.yaml",
+            "user/root/repo/Cargo.toml",
+            "user/root/repo/README.md",
+            "user/root/repo/python/cli/__init__.py",
+            "user/root/repo/scrap/some_file.rs",
+            "user/root/repo/src/core/some_file.rs",
+            "user/root/repo/src/core/some_file2.rs",
+        ]);
+
+        assert_eq!(actual_result, expected_result);
+        for pruned in [".venv", ".pytest_cache", "target"] {
+            let operation = format!("read_dir: `user/root/repo/{pruned}`");
+            assert!(!file_sys.operations.contains(&operation), "{operation}");
+        }
+    }
+
+    #[test]
+    fn test_walk_reads_only_listed_ignore_files() {
+        let files = vec![
+            ("repo/.gitignore", "*.log\n"),
+            ("repo/a/.gitignore", "!keep.log\n"),
+            ("repo/a/keep.log", ""),
+            ("repo/a/b/keep.log", ""),
+            ("repo/c/keep.log", ""),
+            ("repo/c/d/main.rs", ""),
+        ]
+        .into_iter()
+        .map(|(k, v)| (PathBuf::from(k), v.to_string()))
+        .collect::<HashMap<PathBuf, String>>();
+        let mut file_sys = FakeFileSystem::new(files);
+        let mut layers = vec![IgnoreLayer::new(".gitignore")];
+
+        let actual_result = walk(
+            &mut file_sys,
+            &PathBuf::from("repo"),
+            &to_hashset(Vec::<&str>::new()),
+            false,
+            SymlinkMode::List,
+            &mut layers,
+        )
+        .paths;
+
+        let expected_result = to_pathbufs(vec![
+            "repo/.gitignore",
+            "repo/a/.gitignore",
+            "repo/a/b/keep.log",
+            "repo/a/keep.log",
+            "repo/c/d/main.rs",
+        ]);
+
+        assert_eq!(actual_result, expected_result);
+        for dir in ["repo/a/b", "repo/c", "repo/c/d"] {
+            let operation = format!("read: `{dir}/.gitignore`");
+            assert!(!file_sys.operations.contains(&operation), "{operation}");
+        }
+    }
+
+    #[test_case(
+        SymlinkMode::Skip,
+        vec!["repo/pkg/a.toml", "repo/shared/config.toml"],
+        vec![] ;
+        "Ensure skip leaves symlinks out"
+    )]
+    #[test_case(
+        SymlinkMode::Plain,
+        vec!["repo/pkg/a.toml", "repo/pkg/shared", "repo/shared/config.toml", "repo/shared/loop"],
+        vec![] ;
+        "Ensure plain maps symlinks as leaves without their targets"
+    )]
+    #[test_case(
+        SymlinkMode::List,
+        vec!["repo/pkg/a.toml", "repo/pkg/shared", "repo/shared/config.toml", "repo/shared/loop"],
+        vec![("pkg/shared", "repo/shared"), ("shared/loop", "repo")] ;
+        "Ensure list maps symlinks as leaves with their targets"
+    )]
+    #[test_case(
+        SymlinkMode::Follow,
+        vec![
+            "repo/pkg/a.toml",
+            "repo/pkg/shared/config.toml",
+            "repo/pkg/shared/loop",
+            "repo/shared/config.toml",
+            "repo/shared/loop",
+        ],
+        vec![("pkg/shared/loop", "repo"), ("shared/loop", "repo")] ;
+        "Ensure follow enters linked directories but not loops"
+    )]
+    fn test_walk_symlinks(
+        symlinks: SymlinkMode,
+        expected_paths: Vec<&str>,
+        expected_links: Vec<(&str, &str)>,
+    ) {
+        let files = vec!["repo/pkg/a.toml", "repo/shared/config.toml"]
+            .into_iter()
+            .map(|p| (PathBuf::from(p), String::new()))
+            .collect::<HashMap<PathBuf, String>>();
+        let mut file_sys = FakeFileSystem::new(files);
+        file_sys.symlinks.extend([
+            (
+                PathBuf::from("repo/pkg/shared"),
+                PathBuf::from("repo/shared"),
+            ),
+            (PathBuf::from("repo/shared/loop"), PathBuf::from("repo")),
+        ]);
+
+        let actual_result = walk(
+            &mut file_sys,
+            &PathBuf::from("repo"),
+            &to_hashset(Vec::<&str>::new()),
+            false,
+            symlinks,
+            &mut [],
+        );
+
+        let expected_links = expected_links
+            .into_iter()
+            .map(|(k, v)| (PathBuf::from(k), PathBuf::from(v)))
+            .collect::<HashMap<PathBuf, PathBuf>>();
+
+        assert_eq!(actual_result.paths, to_pathbufs(expected_paths));
+        assert_eq!(actual_result.links, expected_links);
+    }
+}
@@ -1,2 +1,3 @@
+#[cfg(feature = "python")]
 pub mod api;
 pub mod core;
@@ -0,0 +1,163 @@
+use clap::Parser;
+use repo_mapper_rs::core::{
+    self,
+    adapters::RealFileSystem,
+    config::Options,
+    domain::{InsertPosition, MetadataColumn, RenderMode, SymlinkMode},
+};
+use std::{path, process::ExitCode};
+
+/// Maps the files of a repo into a tree in its README. Options not given here are read from
+/// `repo-mapper.toml`, `[tool.repo-mapper]` in `pyproject.toml` or
+/// `[package.metadata.repo-mapper]` in `Cargo.toml` in the repo root.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// Path to the root of the repo to generate the map for.
+    #[arg(long, value_parser = absolute)]
+    repo_root: String,
+    /// Path to the readme file to add the map to. Not needed with `--stdout`.
+    #[arg(long, value_parser = absolute)]
+    readme_path: Option<String>,
+    /// Path to the .gitignore. Defaults to `<repo-root>/.gitignore` if it exists.
+    #[arg(long, value_parser = absolute)]
+    gitignore_path: Option<String>,
+    /// A comma separated string of extensions to keep. E.g. 'py,rs,toml'. Defaults to:
+    /// 'py,md,toml,lock,yaml,ipynb'.
+    #[arg(long, value_delimiter = ',')]
+    allowed_exts: Option<Vec<String>>,
+    /// A comma separated string of directories to ignore. E.g. '.venv,target'. Defaults to:
+    /// '.git,.venv,build,dist'.
+    #[arg(long, value_delimiter = ',')]
+    ignore_dirs: Option<Vec<String>>,
+    /// Flag to ignore hidden files. E.g. those that start with a '.' like '.env'.
+    #[arg(long, overrides_with = "no_ignore_hidden")]
+    ignore_hidden: bool,
+    /// Unsets `--ignore-hidden`, e.g. if the config sets it.
+    #[arg(long, overrides_with = "ignore_hidden")]
+    no_ignore_hidden: bool,
+    /// Flag to only map directories instead of files.
+    #[arg(long, overrides_with = "no_dirs_only")]
+    dirs_only: bool,
+    /// Unsets `--dirs-only`, e.g. if the config sets it.
+    #[arg(long, overrides_with = "dirs_only")]
+    no_dirs_only: bool,
+    /// Flag to also respect `.git/info/exclude` and the global `core.excludesFile`.
+    #[arg(long, overrides_with = "no_git_excludes")]
+    git_excludes: bool,
+    /// Unsets `--git-excludes`, e.g. if the config sets it.
+    #[arg(long, overrides_with = "git_excludes")]
+    no_git_excludes: bool,
+    /// A comma separated string of extra ignore files in gitignore syntax to respect besides
+    /// `.repomapignore`, only read from the repo root. E.g. '.dockerignore,.npmignore'.
+    #[arg(long, value_delimiter = ',')]
+    ignore_files: Option<Vec<String>>,
+    /// Flag to map exactly the files in the git index (`git ls-files`) instead of walking the
+    /// directory. `.gitignore` rules are not applied.
+    #[arg(long, overrides_with = "no_git_tracked")]
+    git_tracked: bool,
+    /// Unsets `--git-tracked`, e.g. if the config sets it.
+    #[arg(long, overrides_with = "git_tracked")]
+    no_git_tracked: bool,
+    /// How to map symlinks: 'skip' leaves them out, 'plain' shows them as entries, 'list'
+    /// shows them as entries annotated with their target, 'follow' maps what they point to
+    /// without looping. Defaults to 'plain'.
+    #[arg(long)]
+    symlinks: Option<SymlinkMode>,
+    /// Flag to fail without modifying the README if any directory, entry or ignore file could
+    /// not be read, instead of only warning.
+    #[arg(long, overrides_with = "no_strict")]
+    strict: bool,
+    /// Unsets `--strict`, e.g. if the config sets it.
+    #[arg(long, overrides_with = "strict")]
+    no_strict: bool,
+    /// The heading a new map is put under. Defaults to 'Repo map'.
+    #[arg(long)]
+    heading: Option<String>,
+    /// The level of the heading a new map is put under, from 1 to 6. Defaults to 1.
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=6))]
+    heading_level: Option<u8>,
+    /// Where to put a new map: 'end', 'start' or 'after:<heading>' for the end of that
+    /// heading's section, after any of its subsections. Defaults to 'end'.
+    #[arg(long)]
+    insert_position: Option<InsertPosition>,
+    /// How many levels of the tree to map, deeper entries are left out. 0 only summarises
+    /// the root's entries.
+    #[arg(long)]
+    max_depth: Option<usize>,
+    /// How many entries of a directory to map, the rest are summarised on one line. E.g.
+    /// '… 240 more files (212 .py, 28 .json)'.
+    #[arg(long)]
+    max_children: Option<usize>,
+    /// Flag to annotate files with the first line of their Python module docstring, Rust `//!`
+    /// comment, leading JS/TS JSDoc block or Markdown H1.
+    #[arg(long, overrides_with = "no_annotate")]
+    annotate: bool,
+    /// Unsets `--annotate`, e.g. if the config sets it.
+    #[arg(long, overrides_with = "annotate")]
+    no_annotate: bool,
+    /// A comma separated string of metadata columns to add after each entry: 'size', 'lines'
+    /// and/or 'mtime'. Directories show the totals of the files below them.
+    #[arg(long, value_delimiter = ',')]
+    metadata: Option<Vec<MetadataColumn>>,
+    /// How to render the map in a Markdown README: 'code' as a tree in a code block, or
+    /// clickable as 'list', a nested list of links, or 'html', a tree in a `<pre>` block
+    /// with links. Defaults to 'code'.
+    #[arg(long)]
+    render: Option<RenderMode>,
+    /// Flag to only check the map is up to date, printing a diff and failing if it is stale
+    /// without modifying the README. Useful in CI.
+    #[arg(long)]
+    check: bool,
+    /// Flag to print the map to stdout instead of reading or modifying the README.
+    #[arg(long, conflicts_with = "check")]
+    stdout: bool,
+}
+
+fn absolute(path: &str) -> Result<String, String> {
+    path::absolute(path)
+        .map(|path| path.to_string_lossy().to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// The value of a flag that can be negated with `--no-<flag>`, the last one given wins.
+fn flag(on: bool, off: bool) -> Option<bool> {
+    match (on, off) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let mut file_sys = RealFileSystem;
+
+    // flags left unset let the config decide
+    let options = Options {
+        readme_path: cli.readme_path,
+        gitignore_path: cli.gitignore_path,
+        allowed_exts: cli.allowed_exts,
+        ignore_dirs: cli.ignore_dirs,
+        ignore_hidden: flag(cli.ignore_hidden, cli.no_ignore_hidden),
+        dirs_only: flag(cli.dirs_only, cli.no_dirs_only),
+        git_excludes: flag(cli.git_excludes, cli.no_git_excludes),
+        ignore_files: cli.ignore_files,
+        git_tracked: flag(cli.git_tracked, cli.no_git_tracked),
+        symlinks: cli.symlinks,
+        strict: flag(cli.strict, cli.no_strict),
+        heading: cli.heading,
+        heading_level: cli.heading_level,
+        insert_position: cli.insert_position,
+        max_depth: cli.max_depth,
+        max_children: cli.max_children,
+        annotate: flag(cli.annotate, cli.no_annotate),
+        metadata: cli.metadata,
+        render: cli.render,
+        ..Options::default()
+    };
+
+    match core::main(&mut file_sys, cli.repo_root, options, cli.check, cli.stdout) {
+        Ok(ret_code) | Err(ret_code) => ExitCode::from(ret_code.code()),
+    }
+}
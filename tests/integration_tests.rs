@@ -1,12 +1,52 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::path::{Path, PathBuf};
 
 use repo_mapper_rs::core::{
-    adapters::FakeFileSystem, converters::to_strings, domain::RetCode, main,
+    adapters::FakeFileSystem,
+    config::Options,
+    converters::to_strings,
+    domain::{InsertPosition, MetadataColumn, RenderMode, RetCode, SymlinkMode},
+    main,
 };
 use test_case::test_case;
 
+const REPO_ROOT: &str = "fake/repo/root";
+
+/// The path of `path` in the fake repo.
+fn repo_path(path: &str) -> String {
+    Path::new(REPO_ROOT)
+        .join(path)
+        .to_string_lossy()
+        .to_string()
+}
+
+/// A fake repo holding `files`, given as their path in the repo and contents.
+fn fake_repo(files: &[(&str, &str)]) -> FakeFileSystem {
+    FakeFileSystem::new(
+        files
+            .iter()
+            .map(|(path, contents)| (PathBuf::from(repo_path(path)), contents.to_string()))
+            .collect(),
+    )
+}
+
+/// Maps the fake repo with `options` as given on the command line.
+fn run(
+    file_sys: &mut FakeFileSystem,
+    options: Options,
+    check: bool,
+    stdout: bool,
+) -> Result<RetCode, RetCode> {
+    main(file_sys, REPO_ROOT.to_string(), options, check, stdout)
+}
+
+/// The contents of a file in the fake repo.
+fn read<'a>(file_sys: &'a FakeFileSystem, path: &str) -> &'a str {
+    &file_sys.files[&PathBuf::from(repo_path(path))]
+}
+
 #[test_case(
-    "fake/repo/root/README.md", "fake/repo/root/.gitignore",
+    "README.md",
+    Some(".gitignore"),
     vec!["rs", "md", "toml"],
     vec![".venv", "target"],
     true, false,
@@ -16,7 +56,8 @@ use test_case::test_case;
     "Ensure returns Ok(RetCode::NoModification)) when README is not modified"
 )]
 #[test_case(
-    "fake/repo/root/README.md", "fake/repo/root/.gitignore",
+    "README.md",
+    Some(".gitignore"),
     vec!["rs", "md", "toml"],
     vec![".venv", "target"],
     true, true,
@@ -26,8 +67,8 @@ use test_case::test_case;
     "Ensure only shows directories if dirs_only is true"
 )]
 #[test_case(
-    "fake/repo/root/README.md",
-    "fake/repo/root/.gitignore",
+    "README.md",
+    Some(".gitignore"),
     vec!["rs", "md", "toml", "py"],
     vec![],
     true, false,
@@ -37,41 +78,41 @@ use test_case::test_case;
     "Ensure doesn't ignore directories if given empty vec"
 )]
 #[test_case(
-    "fake/repo/root/README.md",
-    "fake/repo/root/.gitignore",
+    "README.md",
+    Some(".gitignore"),
     vec![],
     vec![".venv", "src"],
     true, false,
     "# Some readme\n",
     Ok(RetCode::ModifiedReadme),
-    "# Some readme\n\n\n# Repo map\n```\n├── Cargo.toml\n├── README.md\n└── scratch.py\n::\n```" ;
+    "# Some readme\n\n# Repo map\n```\n├── Cargo.toml\n├── README.md\n└── scratch.py\n::\n```" ;
     "Ensure return Ok(RetCode::ModifiedReadme) if it modifies the README"
 )]
 #[test_case(
-    "fake/repo/root/README.md",
-    "fake/repo/root/.gitignore",
+    "README.md",
+    Some(".gitignore"),
     vec![],
     vec![".venv", "src"],
     false, false,
     "# Some readme\n",
     Ok(RetCode::ModifiedReadme),
-    "# Some readme\n\n\n# Repo map\n```\n├── secrets\n│   └── .env\n├── .gitignore\n├── Cargo.toml\n├── README.md\n└── scratch.py\n::\n```" ;
+    "# Some readme\n\n# Repo map\n```\n├── secrets\n│   └── .env\n├── .gitignore\n├── Cargo.toml\n├── README.md\n└── scratch.py\n::\n```" ;
     "Ensure does not skip hidden file"
 )]
 #[test_case(
-    "fake/repo/root/WRONG_README.txt",
-    "fake/repo/root/.gitignore",
-    vec![],
+    "README.md",
+    None,
     vec![],
+    vec![".venv", "src"],
     true, false,
     "# Some readme\n",
-    Err(RetCode::InvalidFilename),
-    "# Some readme\n" ;
-    "Ensure Err(RetCode::FailedParsingFile) if not pointed to a valid README"
+    Ok(RetCode::ModifiedReadme),
+    "# Some readme\n\n# Repo map\n```\n├── Cargo.toml\n├── README.md\n└── scratch.py\n::\n```" ;
+    "Ensure discovers the root .gitignore if no path is given"
 )]
 #[test_case(
-    "fake/repo/root/docs/README.md",
-    "fake/repo/root/.gitignore",
+    "docs/README.md",
+    Some(".gitignore"),
     vec![],
     vec![],
     true, false,
@@ -81,8 +122,8 @@ use test_case::test_case;
     "Ensure Err(FAILURE) if not pointed to an invalid file"
 )]
 #[test_case(
-    "fake/repo/root/README.md",
-    "fake/repo/root/.gitdonotignore",
+    "README.md",
+    Some(".gitdonotignore"),
     vec![],
     vec![],
     true, false,
@@ -94,7 +135,7 @@ use test_case::test_case;
 #[allow(clippy::too_many_arguments)]
 fn test_modify_readme(
     readme_path: &str,
-    gitignore_path: &str,
+    gitignore_path: Option<&str>,
     allowed_exts: Vec<&str>,
     ignore_dirs: Vec<&str>,
     ignore_hidden: bool,
@@ -103,45 +144,753 @@ fn test_modify_readme(
     expected_result: Result<RetCode, RetCode>,
     expected_readme: &str,
 ) {
-    let files = vec![
-        ("fake/repo/root/src/main.rs", "let x = 1;"),
-        ("fake/repo/root/src/lib.rs", "use std;"),
-        ("fake/repo/root/Cargo.toml", ""),
-        ("fake/repo/root/README.md", current_readme),
-        ("fake/repo/root/.gitignore", "target/"),
-        ("fake/repo/root/target/some_build.rs", ""),
-        ("fake/repo/root/.venv/site-packages/some_package.py", ""),
-        ("fake/repo/root/scratch.py", ""),
-        ("fake/repo/root/secrets/.env", ""),
-    ]
-    .into_iter()
-    .map(|(k, v)| (PathBuf::from(k), v.to_string()))
-    .collect::<HashMap<PathBuf, String>>();
-
-    let mut file_sys = FakeFileSystem::new(files);
-
-    let repo_root = "fake/repo/root".to_string();
-    let readme_path = readme_path.to_string();
-    let gitignore_path = gitignore_path.to_string();
+    let mut file_sys = fake_repo(&[
+        ("src/main.rs", "let x = 1;"),
+        ("src/lib.rs", "use std;"),
+        ("Cargo.toml", ""),
+        ("README.md", current_readme),
+        (".gitignore", "target/"),
+        ("target/some_build.rs", ""),
+        (".venv/site-packages/some_package.py", ""),
+        ("scratch.py", ""),
+        ("secrets/.env", ""),
+    ]);
+
+    let readme_path = Some(repo_path(readme_path));
+    let gitignore_path = gitignore_path.map(repo_path);
     let allowed_exts = to_strings(allowed_exts);
     let ignore_dirs = to_strings(ignore_dirs);
 
-    let exit_code = main(
+    let exit_code = run(
+        &mut file_sys,
+        Options {
+            readme_path,
+            gitignore_path,
+            allowed_exts: Some(allowed_exts),
+            ignore_dirs: Some(ignore_dirs),
+            ignore_hidden: Some(ignore_hidden),
+            dirs_only: Some(dirs_only),
+            ..Options::default()
+        },
+        false,
+        false,
+    );
+
+    assert_eq!(exit_code, expected_result);
+    assert_eq!(read(&file_sys, "README.md"), expected_readme);
+}
+
+#[test]
+fn test_nested_gitignores() {
+    let mut file_sys = fake_repo(&[
+        ("README.md", "# Some readme\n"),
+        (".gitignore", "*.log\ntarget/\n"),
+        ("app.log", ""),
+        ("notes.txt", ""),
+        ("docs/.gitignore", "*.txt\n!keep.log\n/build/\n"),
+        ("docs/guide.md", ""),
+        ("docs/notes.txt", ""),
+        ("docs/keep.log", ""),
+        ("docs/build/out.md", ""),
+        ("docs/api/build/out.md", ""),
+        ("scratch/.gitignore", "*\n"),
+        ("scratch/notes.md", ""),
+        ("target/.gitignore", "!*\n"),
+        ("target/some_build.md", ""),
+    ]);
+
+    let exit_code = run(
+        &mut file_sys,
+        Options {
+            readme_path: Some(repo_path("README.md")),
+            gitignore_path: Some(repo_path(".gitignore")),
+            allowed_exts: Some(vec![]),
+            ignore_dirs: Some(vec![]),
+            ignore_hidden: Some(true),
+            ..Options::default()
+        },
+        false,
+        false,
+    );
+
+    assert_eq!(exit_code, Ok(RetCode::ModifiedReadme));
+    assert_eq!(
+        read(&file_sys, "README.md"),
+        "# Some readme\n\n# Repo map\n```\n├── docs\n│   ├── api\n│   │   └── build\n│   │       └── out.md\n│   ├── guide.md\n│   └── keep.log\n├── README.md\n└── notes.txt\n::\n```"
+    );
+}
+
+#[test_case(None, Ok(RetCode::ModifiedReadme) ; "Ensure a missing .gitignore is not an error if no path is given")]
+#[test_case(Some(".gitignore"), Err(RetCode::FailedParsingFile) ; "Ensure a missing .gitignore is an error if the path is given")]
+fn test_missing_gitignore(gitignore_path: Option<&str>, expected_result: Result<RetCode, RetCode>) {
+    let mut file_sys = fake_repo(&[
+        ("README.md", "# Some readme\n"),
+        ("src/lib.rs", ""),
+        ("target/some_build.rs", ""),
+    ]);
+
+    let exit_code = run(
+        &mut file_sys,
+        Options {
+            readme_path: Some(repo_path("README.md")),
+            gitignore_path: gitignore_path.map(repo_path),
+            allowed_exts: Some(vec!["rs".to_string()]),
+            ignore_dirs: Some(vec![]),
+            ignore_hidden: Some(true),
+            ..Options::default()
+        },
+        false,
+        false,
+    );
+
+    assert_eq!(exit_code, expected_result);
+}
+
+#[test_case(vec![], "├── docs\n│   └── guide.md\n├── Dockerfile\n├── README.md\n└── app.py" ; "Ensure .repomapignore is always respected")]
+#[test_case(vec![".dockerignore"], "├── docs\n│   └── guide.md\n├── README.md\n└── app.py" ; "Ensure extra ignore files are layered in from the repo root only")]
+fn test_ignore_files(ignore_files: Vec<&str>, expected_map: &str) {
+    let mut file_sys = fake_repo(&[
+        ("README.md", "# Some readme\n"),
+        (".gitignore", "*.log\n"),
+        (".repomapignore", "fixtures/\n"),
+        (".dockerignore", "Dockerfile\n"),
+        ("app.py", ""),
+        ("app.log", ""),
+        ("Dockerfile", ""),
+        ("fixtures/data.json", ""),
+        ("docs/guide.md", ""),
+        ("docs/.repomapignore", "*.log\n!debug.log\ndraft.md\n"),
+        ("docs/.dockerignore", "guide.md\n"),
+        ("docs/debug.log", ""),
+        ("docs/draft.md", ""),
+    ]);
+
+    let exit_code = run(
+        &mut file_sys,
+        Options {
+            readme_path: Some(repo_path("README.md")),
+            allowed_exts: Some(vec![]),
+            ignore_dirs: Some(vec![]),
+            ignore_hidden: Some(true),
+            ignore_files: Some(to_strings(ignore_files)),
+            ..Options::default()
+        },
+        false,
+        false,
+    );
+
+    assert_eq!(exit_code, Ok(RetCode::ModifiedReadme));
+    assert_eq!(
+        read(&file_sys, "README.md"),
+        format!("# Some readme\n\n# Repo map\n```\n{expected_map}\n::\n```")
+    );
+}
+
+#[test]
+fn test_git_tracked() {
+    let mut file_sys = fake_repo(&[
+        ("README.md", "# Some readme\n"),
+        (".gitignore", "*.log\n"),
+        (".repomapignore", "fixtures/\n"),
+        ("app.py", ""),
+        ("force_added.log", ""),
+        ("fixtures/data.json", ""),
+    ]);
+
+    let exit_code = run(
+        &mut file_sys,
+        Options {
+            readme_path: Some(repo_path("README.md")),
+            gitignore_path: Some(repo_path("does_not_exist/.gitignore")),
+            allowed_exts: Some(vec![]),
+            ignore_dirs: Some(vec![]),
+            ignore_hidden: Some(true),
+            git_tracked: Some(true),
+            ..Options::default()
+        },
+        false,
+        false,
+    );
+
+    assert_eq!(exit_code, Ok(RetCode::ModifiedReadme));
+    assert!(file_sys
+        .operations
+        .contains(&format!("git ls-files: `{REPO_ROOT}`")));
+    assert_eq!(
+        read(&file_sys, "README.md"),
+        "# Some readme\n\n# Repo map\n```\n├── README.md\n├── app.py\n└── force_added.log\n::\n```"
+    );
+}
+
+#[test_case(false, Ok(RetCode::ModifiedReadme), "# Some readme\n\n# Repo map\n```\n├── src\n│   └── lib.rs\n└── README.md\n::\n```" ; "Ensure unreadable directories are left out of the map")]
+#[test_case(true, Err(RetCode::IncompleteWalk), "# Some readme\n" ; "Ensure unreadable directories fail in strict mode")]
+fn test_walk_errors(
+    strict: bool,
+    expected_result: Result<RetCode, RetCode>,
+    expected_readme: &str,
+) {
+    let mut file_sys = fake_repo(&[
+        ("README.md", "# Some readme\n"),
+        ("src/lib.rs", ""),
+        ("private/secret.rs", ""),
+    ]);
+    file_sys.denied.insert(PathBuf::from(repo_path("private")));
+
+    let exit_code = run(
+        &mut file_sys,
+        Options {
+            readme_path: Some(repo_path("README.md")),
+            allowed_exts: Some(vec![]),
+            ignore_dirs: Some(vec![]),
+            ignore_hidden: Some(true),
+            strict: Some(strict),
+            ..Options::default()
+        },
+        false,
+        false,
+    );
+
+    assert_eq!(exit_code, expected_result);
+    assert_eq!(read(&file_sys, "README.md"), expected_readme);
+}
+
+#[test_case(false, false, Ok(RetCode::ModifiedReadme) ; "Ensure unreadable ignore files are skipped")]
+#[test_case(true, false, Err(RetCode::IncompleteWalk) ; "Ensure unreadable ignore files fail in strict mode")]
+#[test_case(true, true, Err(RetCode::IncompleteWalk) ; "Ensure unreadable ignore files fail in strict mode with git tracked files")]
+fn test_unreadable_ignore_files(
+    strict: bool,
+    git_tracked: bool,
+    expected_result: Result<RetCode, RetCode>,
+) {
+    let mut file_sys = fake_repo(&[
+        ("README.md", "# Some readme\n"),
+        ("src/lib.rs", ""),
+        ("src/.repomapignore", "*.log\n"),
+        ("src/debug.log", ""),
+    ]);
+    file_sys
+        .denied
+        .insert(PathBuf::from(repo_path("src/.repomapignore")));
+
+    let exit_code = run(
+        &mut file_sys,
+        Options {
+            readme_path: Some(repo_path("README.md")),
+            allowed_exts: Some(vec![]),
+            ignore_dirs: Some(vec![]),
+            ignore_hidden: Some(true),
+            git_tracked: Some(git_tracked),
+            strict: Some(strict),
+            ..Options::default()
+        },
+        false,
+        false,
+    );
+
+    assert_eq!(exit_code, expected_result);
+    if expected_result.is_ok() {
+        assert_eq!(
+            read(&file_sys, "README.md"),
+            "# Some readme\n\n# Repo map\n```\n├── src\n│   ├── debug.log\n│   └── lib.rs\n└── README.md\n::\n```"
+        );
+    }
+}
+
+#[test_case(None, Some(vec![]), "├── pkg\n│   ├── a.toml\n│   └── shared\n├── shared\n│   └── config.toml\n└── README.md" ; "Ensure symlinks are plain entries by default")]
+#[test_case(Some(SymlinkMode::List), Some(vec![]), "├── pkg\n│   ├── a.toml\n│   └── shared -> ../shared\n├── shared\n│   └── config.toml\n└── README.md" ; "Ensure list annotates symlinks with their target")]
+#[test_case(Some(SymlinkMode::List), None, "├── pkg\n│   ├── a.toml\n│   └── shared -> ../shared\n├── shared\n│   └── config.toml\n└── README.md" ; "Ensure listed symlinks are kept whatever the allowed extensions")]
+fn test_symlinks(
+    symlinks: Option<SymlinkMode>,
+    allowed_exts: Option<Vec<&str>>,
+    expected_map: &str,
+) {
+    let mut file_sys = fake_repo(&[
+        ("README.md", "# Some readme\n"),
+        ("pkg/a.toml", ""),
+        ("shared/config.toml", ""),
+    ]);
+    file_sys.symlinks.insert(
+        PathBuf::from(repo_path("pkg/shared")),
+        PathBuf::from("../shared"),
+    );
+
+    let exit_code = run(
         &mut file_sys,
-        repo_root,
-        readme_path,
-        gitignore_path,
-        allowed_exts,
-        ignore_dirs,
-        ignore_hidden,
-        dirs_only,
+        Options {
+            readme_path: Some(repo_path("README.md")),
+            allowed_exts: allowed_exts.map(to_strings),
+            ignore_dirs: Some(vec![]),
+            ignore_hidden: Some(true),
+            symlinks,
+            ..Options::default()
+        },
+        false,
+        false,
+    );
+
+    assert_eq!(exit_code, Ok(RetCode::ModifiedReadme));
+    assert_eq!(
+        read(&file_sys, "README.md"),
+        format!("# Some readme\n\n# Repo map\n```\n{expected_map}\n::\n```")
     );
+}
 
-    let readme_pathbuf = PathBuf::from("fake/repo/root/README.md");
+#[test_case("# Some readme\n\n\n# Repo map\n```\n├── README.md\n└── app.py\n::\n```", Ok(RetCode::NoModification) ; "Ensure check passes if the map is up to date")]
+#[test_case("# Some readme\n\n\n# Repo map\n```\n└── README.md\n::\n```", Err(RetCode::OutdatedReadme) ; "Ensure check fails if the map is stale")]
+#[test_case("# Some readme\n", Err(RetCode::OutdatedReadme) ; "Ensure check fails if there is no map")]
+#[test_case("## Layout\n<!-- repo-map:start -->\n```\n├── README.md\n└── app.py\n```\n<!-- repo-map:end -->\nmore", Ok(RetCode::NoModification) ; "Ensure check passes if the map between markers is up to date")]
+#[test_case("## Layout\n<!-- repo-map:start -->\n<!-- repo-map:end -->\nmore", Err(RetCode::OutdatedReadme) ; "Ensure check fails if the markers are empty")]
+fn test_check(current_readme: &str, expected_result: Result<RetCode, RetCode>) {
+    let mut file_sys = fake_repo(&[("README.md", current_readme), ("app.py", "")]);
+
+    let exit_code = run(
+        &mut file_sys,
+        Options {
+            readme_path: Some(repo_path("README.md")),
+            allowed_exts: Some(vec![]),
+            ignore_dirs: Some(vec![]),
+            ignore_hidden: Some(true),
+            ..Options::default()
+        },
+        true,
+        false,
+    );
 
     assert_eq!(exit_code, expected_result);
+    assert!(!file_sys.operations.iter().any(|op| op.starts_with("write")));
+}
+
+#[test]
+fn test_stdout() {
+    let mut file_sys = fake_repo(&[
+        (".gitignore", "*.log\n"),
+        ("app.py", ""),
+        ("app.log", ""),
+        ("src/lib.rs", ""),
+    ]);
+
+    let exit_code = run(
+        &mut file_sys,
+        Options {
+            allowed_exts: Some(to_strings(["py", "log"])),
+            ignore_dirs: Some(vec![]),
+            ignore_hidden: Some(true),
+            ..Options::default()
+        },
+        false,
+        true,
+    );
+
+    assert_eq!(exit_code, Ok(RetCode::NoModification));
+    assert_eq!(file_sys.stdout, "# Repo map\n```\n└── app.py\n::\n```\n");
+    assert!(!file_sys
+        .operations
+        .iter()
+        .any(|op| op.starts_with("write:") || op.contains("README.md")));
+}
+
+#[test_case(None, None, "├── docs\n│   └── guide.md\n└── README.md" ; "Ensure options are read from the config file")]
+#[test_case(Some(vec!["py"]), None, "└── app.py" ; "Ensure command line options override the config file")]
+#[test_case(None, Some(false), "├── docs\n│   ├── .hidden.md\n│   └── guide.md\n└── README.md" ; "Ensure command line flags can unset the config file")]
+fn test_config(allowed_exts: Option<Vec<&str>>, ignore_hidden: Option<bool>, expected_map: &str) {
+    let mut file_sys = fake_repo(&[
+        (
+            "pyproject.toml",
+            "[tool.repo-mapper]\nreadme-path = \"README.md\"\nallowed-exts = [\"md\"]\nignore-hidden = true\n",
+        ),
+        ("README.md", "# Some readme\n"),
+        ("app.py", ""),
+        ("docs/guide.md", ""),
+        ("docs/.hidden.md", ""),
+    ]);
+
+    let exit_code = run(
+        &mut file_sys,
+        Options {
+            allowed_exts: allowed_exts.map(to_strings),
+            ignore_hidden,
+            ..Options::default()
+        },
+        false,
+        false,
+    );
+
+    assert_eq!(exit_code, Ok(RetCode::ModifiedReadme));
+    assert_eq!(
+        read(&file_sys, "README.md"),
+        format!("# Some readme\n\n# Repo map\n```\n{expected_map}\n::\n```")
+    );
+}
+
+#[test]
+fn test_named_blocks() {
+    let mut file_sys = fake_repo(&[
+        (
+            "repo-mapper.toml",
+            "[blocks.overview]\ndirs-only = true\n",
+        ),
+        (
+            "README.md",
+            "# Some readme\n\n# Repo map\n```repo-map:src root=src allowed-exts=rs\n::\n```\n\nMore text",
+        ),
+        ("app.py", ""),
+        ("src/lib.rs", ""),
+        ("src/core/mod.rs", ""),
+        ("src/core/notes.md", ""),
+    ]);
+
+    let exit_code = run(
+        &mut file_sys,
+        Options {
+            readme_path: Some(repo_path("README.md")),
+            allowed_exts: Some(vec![]),
+            ignore_dirs: Some(vec![]),
+            ..Options::default()
+        },
+        false,
+        false,
+    );
+
+    assert_eq!(exit_code, Ok(RetCode::ModifiedReadme));
+    assert_eq!(
+        read(&file_sys, "README.md"),
+        "# Some readme\n\n# Repo map\n```repo-map:src root=src allowed-exts=rs\n├── core\n│   └── mod.rs\n└── lib.rs\n::\n```\n\nMore text\n\n# Repo map\n```repo-map:overview\n└── src\n    └── core\n::\n```"
+    );
+}
+
+#[test_case("./src/", Ok("# Some readme\n\n# Repo map\n```repo-map:src root=./src/\n├── core\n│   └── mod.rs\n└── lib.rs\n::\n```\n") ; "Ensure the root is normalized")]
+#[test_case("../src", Err(RetCode::InvalidConfig) ; "Ensure fails on a root outside the repo")]
+#[test_case("docs", Err(RetCode::InvalidConfig) ; "Ensure fails on a missing root")]
+#[test_case("app.py", Err(RetCode::InvalidConfig) ; "Ensure fails on a file as the root")]
+fn test_block_roots(root: &str, expected_readme: Result<&str, RetCode>) {
+    let current_readme =
+        format!("# Some readme\n\n# Repo map\n```repo-map:src root={root}\n::\n```\n");
+    let mut file_sys = fake_repo(&[
+        ("README.md", &current_readme),
+        ("app.py", ""),
+        ("src/lib.rs", ""),
+        ("src/core/mod.rs", ""),
+    ]);
+
+    let exit_code = run(
+        &mut file_sys,
+        Options {
+            readme_path: Some(repo_path("README.md")),
+            allowed_exts: Some(vec![]),
+            ignore_dirs: Some(vec![]),
+            ..Options::default()
+        },
+        false,
+        false,
+    );
+
+    match expected_readme {
+        Ok(expected_readme) => {
+            assert_eq!(exit_code, Ok(RetCode::ModifiedReadme));
+            assert_eq!(read(&file_sys, "README.md"), expected_readme);
+        }
+        Err(ret_code) => {
+            assert_eq!(exit_code, Err(ret_code));
+            assert_eq!(read(&file_sys, "README.md"), current_readme);
+        }
+    }
+}
+
+#[test]
+fn test_heading_and_insert_position() {
+    let mut file_sys = fake_repo(&[
+        (
+            "repo-mapper.toml",
+            "heading = \"Project layout\"\nheading-level = 2\ninsert-position = \"after:Install\"\n",
+        ),
+        (
+            "README.md",
+            "# Title\n\n## Install\ntext\n\n## Usage\ntext",
+        ),
+        ("app.py", ""),
+    ]);
+    let options = Options {
+        readme_path: Some(repo_path("README.md")),
+        allowed_exts: Some(to_strings(["py"])),
+        ignore_dirs: Some(vec![]),
+        ..Options::default()
+    };
+
+    let exit_code = run(&mut file_sys, options.clone(), false, false);
+
+    assert_eq!(exit_code, Ok(RetCode::ModifiedReadme));
+    assert_eq!(
+        read(&file_sys, "README.md"),
+        "# Title\n\n## Install\ntext\n\n## Project layout\n<!-- repo-map:start -->\n```\n└── app.py\n```\n<!-- repo-map:end -->\n\n## Usage\ntext"
+    );
+
+    let exit_code = run(&mut file_sys, options, true, false);
+    assert_eq!(exit_code, Ok(RetCode::NoModification));
+}
+
+#[test_case(
+    "docs/index.rst",
+    "Docs\n====\n\nIntro\n-----\ntext\n\nUsage\n-----\ntext",
+    "Docs\n====\n\nIntro\n-----\ntext\n\nProject layout\n--------------\n\n.. repo-map:start\n\n.. code-block:: text\n\n   ├── src\n   │   └── lib.rs\n   └── app.py\n\n.. repo-map:end\n\nUsage\n-----\ntext" ;
+    "Ensure inserts a reStructuredText block"
+)]
+#[test_case(
+    "docs/index.adoc",
+    "= Docs\n\n== Intro\ntext\n\n== Usage\ntext",
+    "= Docs\n\n== Intro\ntext\n\n== Project layout\n// repo-map:start\n----\n├── src\n│   └── lib.rs\n└── app.py\n----\n// repo-map:end\n\n== Usage\ntext" ;
+    "Ensure inserts an AsciiDoc block"
+)]
+#[test_case(
+    "CONTRIBUTING.md",
+    "# Contributing\n\n## Intro\ntext",
+    "# Contributing\n\n## Intro\ntext\n\n## Project layout\n<!-- repo-map:start -->\n```\n├── src\n│   └── lib.rs\n└── app.py\n```\n<!-- repo-map:end -->" ;
+    "Ensure inserts into any markdown file"
+)]
+fn test_doc_formats(target_path: &str, current_doc: &str, expected_doc: &str) {
+    let mut file_sys = fake_repo(&[
+        (target_path, current_doc),
+        ("app.py", ""),
+        ("src/lib.rs", ""),
+    ]);
+    let options = Options {
+        readme_path: Some(repo_path(target_path)),
+        allowed_exts: Some(to_strings(["py", "rs"])),
+        ignore_dirs: Some(vec![]),
+        heading: Some("Project layout".to_string()),
+        heading_level: Some(2),
+        insert_position: Some(InsertPosition::After("Intro".to_string())),
+        ..Options::default()
+    };
+
+    let exit_code = run(&mut file_sys, options.clone(), false, false);
+
+    assert_eq!(exit_code, Ok(RetCode::ModifiedReadme));
+    assert_eq!(read(&file_sys, target_path), expected_doc);
+
+    let exit_code = run(&mut file_sys, options, true, false);
+    assert_eq!(exit_code, Ok(RetCode::NoModification));
+}
+
+#[test]
+fn test_render_limits() {
+    let mut file_sys = fake_repo(&[
+        (
+            "repo-mapper.toml",
+            "max-children = 1\n\n[blocks.all]\n\n[blocks.top]\nmax-depth = 1\nmax-children = 5\n",
+        ),
+        ("app.py", ""),
+        ("cli.py", ""),
+        ("src/lib.rs", ""),
+    ]);
+
+    let exit_code = run(
+        &mut file_sys,
+        Options {
+            allowed_exts: Some(to_strings(["py", "rs"])),
+            ignore_dirs: Some(vec![]),
+            ..Options::default()
+        },
+        false,
+        true,
+    );
+
+    assert_eq!(exit_code, Ok(RetCode::NoModification));
+    assert_eq!(
+        file_sys.stdout,
+        "# Repo map\n```repo-map:all\n├── src\n│   └── lib.rs\n└── … 2 more files (2 .py)\n::\n```\n\n# Repo map\n```repo-map:top\n├── src\n├── app.py\n└── cli.py\n::\n```\n"
+    );
+}
+
+#[test]
+fn test_annotate() {
+    let mut file_sys = fake_repo(&[
+        ("src/app.py", "\"\"\"Entry point of the app.\"\"\"\n"),
+        ("src/utils.py", "import os\n"),
+        ("src/ui/index.ts", "/** Renders the UI. */\n"),
+        ("README.md", "# Some readme\n"),
+    ]);
+
+    let exit_code = run(
+        &mut file_sys,
+        Options {
+            allowed_exts: Some(to_strings(["py", "ts", "md"])),
+            ignore_dirs: Some(vec![]),
+            annotate: Some(true),
+            ..Options::default()
+        },
+        false,
+        true,
+    );
+
+    assert_eq!(exit_code, Ok(RetCode::NoModification));
+    assert_eq!(
+        file_sys.stdout,
+        "# Repo map\n```\n├── src\n│   ├── ui\n│   │   └── index.ts  # Renders the UI.\n│   ├── app.py        # Entry point of the app.\n│   └── utils.py\n└── README.md         # Some readme\n::\n```\n"
+    );
+}
+
+#[test]
+fn test_annotate_blocks() {
+    let mut file_sys = fake_repo(&[
+        (
+            "repo-mapper.toml",
+            "[blocks.overview]\n\n[blocks.src]\nroot = \"src\"\n",
+        ),
+        ("src/app.py", "\"\"\"Entry point of the app.\"\"\"\n"),
+        ("setup.py", "\"\"\"Packaging.\"\"\"\n"),
+    ]);
+
+    let exit_code = run(
+        &mut file_sys,
+        Options {
+            allowed_exts: Some(to_strings(["py"])),
+            ignore_dirs: Some(vec![]),
+            annotate: Some(true),
+            ..Options::default()
+        },
+        false,
+        true,
+    );
+
+    assert_eq!(exit_code, Ok(RetCode::NoModification));
+    assert_eq!(
+        file_sys.stdout,
+        "# Repo map\n```repo-map:overview\n├── src\n│   └── app.py  # Entry point of the app.\n└── setup.py    # Packaging.\n::\n```\n\n# Repo map\n```repo-map:src\n└── app.py  # Entry point of the app.\n::\n```\n"
+    );
+    let reads = file_sys
+        .operations
+        .iter()
+        .filter(|op| *op == &format!("read: `{REPO_ROOT}/src/app.py`"))
+        .count();
+    assert_eq!(reads, 1);
+}
+
+#[test]
+fn test_descriptions() {
+    let mut file_sys = fake_repo(&[
+        (
+            "src/app.py",
+            "\"\"\"Entry point of the app.\"\"\"\n",
+        ),
+        ("src/utils.py", "import os\n"),
+        ("README.md", "# Some readme\n"),
+        (
+            "repo-map.descriptions.toml",
+            "\"src/\" = \"The app\"\n\"src/utils.py\" = \"Shared helpers\"\n\"README.md\" = \"Start here\"\n\"src/old.py\" = \"Was removed\"\n",
+        ),
+    ]);
+
+    let exit_code = run(
+        &mut file_sys,
+        Options {
+            allowed_exts: Some(to_strings(["py", "md"])),
+            ignore_dirs: Some(vec![]),
+            annotate: Some(true),
+            ..Options::default()
+        },
+        false,
+        true,
+    );
+
+    assert_eq!(exit_code, Ok(RetCode::NoModification));
+    assert_eq!(
+        file_sys.stdout,
+        "# Repo map\n```\n├── src           # The app\n│   ├── app.py    # Entry point of the app.\n│   └── utils.py  # Shared helpers\n└── README.md     # Start here\n::\n```\n"
+    );
+}
+
+#[test]
+fn test_metadata() {
+    let mut file_sys = fake_repo(&[
+        ("src/app.py", "import os\n\nprint(os.name)\n"),
+        ("src/ui/index.ts", "export {};\n"),
+        ("README.md", "# Some readme\n"),
+    ]);
+
+    let exit_code = run(
+        &mut file_sys,
+        Options {
+            allowed_exts: Some(to_strings(["py", "ts", "md"])),
+            ignore_dirs: Some(vec![]),
+            metadata: Some(vec![MetadataColumn::Size, MetadataColumn::Lines]),
+            ..Options::default()
+        },
+        false,
+        true,
+    );
+
+    assert_eq!(exit_code, Ok(RetCode::NoModification));
     assert_eq!(
-        file_sys.files.get(&readme_pathbuf).unwrap().to_owned(),
-        expected_readme.to_string()
+        file_sys.stdout,
+        "# Repo map\n```\n├── src               37 B  4 lines\n│   ├── ui            11 B   1 line\n│   │   └── index.ts  11 B   1 line\n│   └── app.py        26 B  3 lines\n└── README.md         14 B   1 line\n::\n```\n"
     );
 }
+
+#[test_case(
+    "docs/README.md",
+    "# Docs\n\n<!-- repo-map:start root=src -->\n```\n```\n<!-- repo-map:end -->\n",
+    RenderMode::List,
+    Ok("# Docs\n\n<!-- repo-map:start root=src -->\n- [ui](../src/ui)\n  - [index.ts](../src/ui/index.ts)\n- [app.py](../src/app.py)\n<!-- repo-map:end -->\n") ;
+    "Ensure links a list relative to the README"
+)]
+#[test_case(
+    "README.md",
+    "# Some readme",
+    RenderMode::Html,
+    Ok("# Some readme\n\n<!-- repo-map:start -->\n<pre>\n├── <a href=\"src\">src</a>\n│   ├── <a href=\"src/ui\">ui</a>\n│   │   └── <a href=\"src/ui/index.ts\">index.ts</a>\n│   └── <a href=\"src/app.py\">app.py</a>\n└── <a href=\"README.md\">README.md</a>\n</pre>\n<!-- repo-map:end -->") ;
+    "Ensure appends a linked tree between markers"
+)]
+#[test_case(
+    "README.md",
+    "# Some readme\n\n# Repo map\n```\n::\n```\n",
+    RenderMode::List,
+    Ok("# Some readme\n\n# Repo map\n<!-- repo-map:start -->\n- [src](src)\n  - [ui](src/ui)\n    - [index.ts](src/ui/index.ts)\n  - [app.py](src/app.py)\n- [README.md](README.md)\n<!-- repo-map:end -->\n") ;
+    "Ensure rewrites a legacy block between markers"
+)]
+#[test_case(
+    "README.md",
+    "# Some readme\n\n# Repo map\n```repo-map:src root=src\n::\n```\n",
+    RenderMode::Html,
+    Ok("# Some readme\n\n# Repo map\n<!-- repo-map:start:src root=src -->\n<pre>\n├── <a href=\"src/ui\">ui</a>\n│   └── <a href=\"src/ui/index.ts\">index.ts</a>\n└── <a href=\"src/app.py\">app.py</a>\n</pre>\n<!-- repo-map:end -->\n") ;
+    "Ensure keeps the name and settings of a rewritten legacy block"
+)]
+#[test_case(
+    "README.rst",
+    "Some readme\n===========\n",
+    RenderMode::Html,
+    Err(RetCode::InvalidConfig) ;
+    "Ensure fails outside markdown"
+)]
+fn test_clickable(
+    readme_path: &str,
+    current_readme: &str,
+    render: RenderMode,
+    expected_readme: Result<&str, RetCode>,
+) {
+    let mut file_sys = fake_repo(&[
+        (readme_path, current_readme),
+        ("src/app.py", ""),
+        ("src/ui/index.ts", ""),
+    ]);
+
+    let options = Options {
+        readme_path: Some(repo_path(readme_path)),
+        allowed_exts: Some(to_strings(["py", "ts", "md"])),
+        ignore_dirs: Some(vec![]),
+        render: Some(render),
+        ..Options::default()
+    };
+
+    let exit_code = run(&mut file_sys, options.clone(), false, false);
+
+    match expected_readme {
+        Ok(expected_readme) => {
+            assert_eq!(exit_code, Ok(RetCode::ModifiedReadme));
+            assert_eq!(read(&file_sys, readme_path), expected_readme);
+
+            let exit_code = run(&mut file_sys, options, true, false);
+            assert_eq!(exit_code, Ok(RetCode::NoModification));
+        }
+        Err(ret_code) => assert_eq!(exit_code, Err(ret_code)),
+    }
+}